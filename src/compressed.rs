@@ -0,0 +1,74 @@
+// Reading gzip, zstd, xz and bzip2 compressed wordlists without unpacking them first
+
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+#[derive(Clone, Copy)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+    Xz,
+    Bzip2,
+}
+
+impl Compression {
+    pub fn name(self) -> &'static str {
+        match self {
+            Compression::Gzip => "gzip",
+            Compression::Zstd => "zstd",
+            Compression::Xz => "xz",
+            Compression::Bzip2 => "bzip2",
+        }
+    }
+}
+
+pub fn detect(path: &str) -> Result<Option<Compression>, Box<dyn Error>> {
+    // Work out if a file is compressed from its magic bytes /*{{{*/
+    let mut magic = Vec::with_capacity(6);
+    File::open(path)?.take(6).read_to_end(&mut magic)?;
+    let compression = match magic.as_slice() {
+        [0x1f, 0x8b, ..] => Compression::Gzip,
+        [0x28, 0xb5, 0x2f, 0xfd, ..] => Compression::Zstd,
+        [0xfd, b'7', b'z', b'X', b'Z', 0x00] => Compression::Xz,
+        [b'B', b'Z', b'h', ..] => Compression::Bzip2,
+        _ => return Ok(None),
+    };
+    Ok(Some(compression))
+}
+/*}}}*/
+
+struct Counted {
+    // Count the compressed bytes as the decompressor reads them /*{{{*/
+    file: File,
+    read: Arc<AtomicUsize>,
+}
+/*}}}*/
+
+impl Read for Counted {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.file.read(buf)?;
+        self.read.fetch_add(n, Ordering::Relaxed);
+        Ok(n)
+    }
+}
+
+pub fn open(
+    path: &str,
+    compression: Compression,
+    read: Arc<AtomicUsize>,
+) -> Result<Box<dyn Read + Send>, Box<dyn Error>> {
+    // A reader giving the decompressed wordlist, adding compressed bytes to read /*{{{*/
+    let file = BufReader::with_capacity(1 << 20, Counted { file: File::open(path)?, read });
+    // wordlists are often several files cat'd together, so read every member/frame
+    let decoder: Box<dyn Read + Send> = match compression {
+        Compression::Gzip => Box::new(flate2::bufread::MultiGzDecoder::new(file)),
+        Compression::Zstd => Box::new(zstd::Decoder::with_buffer(file)?),
+        Compression::Xz => Box::new(xz2::bufread::XzDecoder::new_multi_decoder(file)),
+        Compression::Bzip2 => Box::new(bzip2::bufread::MultiBzDecoder::new(file)),
+    };
+    Ok(decoder)
+}
+/*}}}*/
@@ -0,0 +1,271 @@
+// Turning wordlist candidates into the UTF-16LE that NT hashes are made of
+
+use std::io::Write;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Encoding {
+    Utf8,
+    Latin1,
+    Cp1252,
+    Auto,    // UTF-8, falling back to cp1252 for anything that isn't valid UTF-8
+    Utf16Le, // the wordlist is already encoded, nothing to do
+}
+
+impl Encoding {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "utf-8" | "utf8" => Some(Self::Utf8),
+            "latin-1" | "latin1" | "iso-8859-1" => Some(Self::Latin1),
+            "cp1252" | "windows-1252" => Some(Self::Cp1252),
+            "auto" => Some(Self::Auto),
+            "utf-16le" | "utf16le" | "utf-16" | "utf16" => Some(Self::Utf16Le),
+            _ => None,
+        }
+    }
+}
+
+// The 0x80-0x9F block is where cp1252 differs from latin-1, the five holes
+// map straight through like Windows' own MultiByteToWideChar does
+const CP1252_HIGH: [u16; 32] = [
+    0x20AC, 0x0081, 0x201A, 0x0192, 0x201E, 0x2026, 0x2020, 0x2021, 0x02C6, 0x2030, 0x0160,
+    0x2039, 0x0152, 0x008D, 0x017D, 0x008F, 0x0090, 0x2018, 0x2019, 0x201C, 0x201D, 0x2022,
+    0x2013, 0x2014, 0x02DC, 0x2122, 0x0161, 0x203A, 0x0153, 0x009D, 0x017E, 0x0178,
+];
+
+fn cp1252(byte: u8) -> u16 {
+    match byte {
+        0x80..=0x9F => CP1252_HIGH[(byte - 0x80) as usize],
+        _ => byte as u16,
+    }
+}
+
+fn single_byte(clear: &[u8], out: &mut [u8], map: fn(u8) -> u16) -> Option<usize> {
+    // Every byte is one UTF-16 code unit /*{{{*/
+    if clear.len() * 2 > out.len() {
+        return None;
+    }
+    for (dst, src) in out.chunks_exact_mut(2).zip(clear.iter()) {
+        dst.copy_from_slice(&map(*src).to_le_bytes());
+    }
+    Some(clear.len() * 2)
+}
+/*}}}*/
+
+fn utf8(clear: &[u8], out: &mut [u8]) -> Option<usize> {
+    // Decode UTF-8, anything outside the BMP becomes a surrogate pair /*{{{*/
+    let clear = std::str::from_utf8(clear).ok()?;
+    let mut len = 0;
+    let mut units = [0_u16; 2];
+    for c in clear.chars() {
+        for unit in c.encode_utf16(&mut units) {
+            out.get_mut(len..len + 2)?.copy_from_slice(&unit.to_le_bytes());
+            len += 2;
+        }
+    }
+    Some(len)
+}
+/*}}}*/
+
+pub fn to_utf16le(clear: &[u8], encoding: Encoding, out: &mut [u8]) -> Option<usize> {
+    // Encode a candidate into out, returning the length or None if it can't be /*{{{*/
+    if encoding == Encoding::Utf16Le {
+        out.get_mut(..clear.len())?.copy_from_slice(clear);
+        return Some(clear.len());
+    }
+    // ASCII is the same in all of them, and it's what most wordlists are, so
+    // do that the quick way first
+    if clear.is_ascii() {
+        return single_byte(clear, out, u16::from);
+    }
+    match encoding {
+        Encoding::Utf8 => utf8(clear, out),
+        Encoding::Latin1 => single_byte(clear, out, u16::from),
+        Encoding::Cp1252 => single_byte(clear, out, cp1252),
+        Encoding::Auto => utf8(clear, out).or_else(|| single_byte(clear, out, cp1252)),
+        Encoding::Utf16Le => unreachable!(),
+    }
+}
+/*}}}*/
+
+pub fn utf16le_lines(chunk: &[u8]) -> impl Iterator<Item = &[u8]> {
+    // Split a UTF-16LE chunk on newlines, dropping CRs, the BOM and empty lines /*{{{*/
+    let (units, _) = chunk.as_chunks::<2>();
+    units
+        .split(|unit| *unit == [10, 0])
+        .map(|line| {
+            let line = line.strip_prefix(&[[0xFF, 0xFE]]).unwrap_or(line);
+            let line = line.strip_suffix(&[[13, 0]]).unwrap_or(line);
+            line.as_flattened()
+        })
+        .filter(|line| !line.is_empty())
+}
+/*}}}*/
+
+pub fn utf16le_to_utf8(clear: &[u8]) -> Vec<u8> {
+    // Decode a UTF-16LE candidate so we can print it /*{{{*/
+    let units = clear
+        .chunks_exact(2)
+        .map(|unit| u16::from_le_bytes([unit[0], unit[1]]));
+    char::decode_utf16(units)
+        .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+        .collect::<String>()
+        .into_bytes()
+}
+/*}}}*/
+
+pub fn unhex<'a>(clear: &'a [u8], out: &'a mut Vec<u8>) -> &'a [u8] {
+    // Decode a hashcat style $HEX[6d7970617373] candidate into out, or leave it be /*{{{*/
+    let Some(inner) = clear
+        .strip_prefix(b"$HEX[")
+        .and_then(|c| c.strip_suffix(b"]"))
+    else {
+        return clear;
+    };
+    out.clear();
+    out.resize(inner.len() / 2, 0);
+    match hex::decode_to_slice(inner, out) {
+        Ok(()) => out,
+        Err(_) => clear,
+    }
+}
+/*}}}*/
+
+pub fn needs_hex(clear: &[u8]) -> bool {
+    // Would printing this clear as is break a hash:clear line parser /*{{{*/
+    clear.starts_with(b"$HEX[")
+        || clear.iter().any(|c| *c == b':' || c.is_ascii_control())
+        || std::str::from_utf8(clear).is_err()
+}
+/*}}}*/
+
+pub fn write_hex(out: &mut Vec<u8>, clear: &[u8]) {
+    // Add a clear to a line, as $HEX[] if it would break it up /*{{{*/
+    if needs_hex(clear) {
+        out.extend_from_slice(b"$HEX[");
+        //writing each character is faster than doing it in one go
+        for x in clear {
+            write!(out, "{:02x}", x).unwrap();
+        }
+        out.extend_from_slice(b"]");
+    } else {
+        out.extend_from_slice(clear);
+    }
+}
+/*}}}*/
+
+pub fn to_text(clear: &[u8], encoding: Encoding) -> String {
+    // Decode a candidate for output that has to be text, like JSON /*{{{*/
+    // every byte is at most one UTF-16 code unit
+    let mut utf16 = vec![0_u8; clear.len() * 2];
+    match to_utf16le(clear, encoding, &mut utf16) {
+        Some(len) => String::from_utf8(utf16le_to_utf8(&utf16[..len])).unwrap(),
+        None => String::from_utf8_lossy(clear).into_owned(),
+    }
+}
+/*}}}*/
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::md4;
+
+    fn utf16(clear: &[u8], encoding: Encoding) -> Option<Vec<u8>> {
+        let mut out = [0_u8; 64];
+        to_utf16le(clear, encoding, &mut out).map(|len| out[..len].to_vec())
+    }
+
+    fn nt(clear: &[u8], encoding: Encoding) -> String {
+        let mut md = md4::MD4::new();
+        md.digest(&utf16(clear, encoding).unwrap());
+        hex::encode(md.get_hash())
+    }
+
+    #[test]
+    fn ascii_is_the_same_in_all_of_them() {
+        for encoding in [Encoding::Utf8, Encoding::Latin1, Encoding::Cp1252, Encoding::Auto] {
+            assert_eq!(nt(b"password", encoding), "8846f7eaee8fb117ad06bdd830b7586c");
+        }
+    }
+
+    #[test]
+    fn astral_characters_become_surrogate_pairs() {
+        let smiley = "\u{1F600}".as_bytes();
+        assert_eq!(utf16(smiley, Encoding::Utf8), Some(vec![0x3D, 0xD8, 0x00, 0xDE]));
+        assert_eq!(nt(smiley, Encoding::Utf8), "4b58a10cc20a4e7d808d218e1f80aabc");
+    }
+
+    #[test]
+    fn cp1252_high_block() {
+        assert_eq!(utf16(&[0x80], Encoding::Cp1252), Some(vec![0xAC, 0x20]));
+        assert_eq!(nt(&[0x80], Encoding::Cp1252), "030926b781938db4365d46adc7cfbcb8");
+        // latin-1 maps it straight through instead
+        assert_eq!(utf16(&[0x80], Encoding::Latin1), Some(vec![0x80, 0x00]));
+        // the holes pass through too
+        for hole in [0x81, 0x8D, 0x8F, 0x90, 0x9D] {
+            assert_eq!(utf16(&[hole], Encoding::Cp1252), Some(vec![hole, 0x00]));
+        }
+    }
+
+    #[test]
+    fn auto_falls_back_to_cp1252() {
+        let hash = "b1db12409c00d1fc586fc48ecadc36a1"; // café
+        assert_eq!(nt("café".as_bytes(), Encoding::Auto), hash);
+        assert_eq!(nt(b"caf\xe9", Encoding::Auto), hash);
+        assert_eq!(utf16(b"\x80", Encoding::Auto), Some(vec![0xAC, 0x20]));
+        // but plain UTF-8 won't have it
+        assert_eq!(utf16(b"caf\xe9", Encoding::Utf8), None);
+    }
+
+    #[test]
+    fn too_long_for_the_buffer() {
+        let mut out = [0_u8; 6];
+        assert_eq!(to_utf16le(b"abc", Encoding::Utf8, &mut out), Some(6));
+        assert_eq!(to_utf16le(b"abcd", Encoding::Utf8, &mut out), None);
+        assert_eq!(to_utf16le("é€".as_bytes(), Encoding::Utf8, &mut out[..2]), None);
+        assert_eq!(to_utf16le(b"\xe9\x80", Encoding::Cp1252, &mut out[..2]), None);
+        assert_eq!(to_utf16le("\u{1F600}".as_bytes(), Encoding::Utf8, &mut out[..2]), None);
+        assert_eq!(to_utf16le(&[0x61, 0x00, 0x62, 0x00], Encoding::Utf16Le, &mut out[..2]), None);
+    }
+
+    #[test]
+    fn utf16le_lines_drop_the_bom_and_crs() {
+        let chunk = b"\xff\xfea\x00b\x00\r\x00\n\x00c\x00\n\x00\n\x00d\x00";
+        let lines: Vec<&[u8]> = utf16le_lines(chunk).collect();
+        assert_eq!(lines, [&b"a\x00b\x00"[..], b"c\x00", b"d\x00"]);
+        assert_eq!(utf16le_to_utf8(lines[0]), b"ab");
+    }
+
+    #[test]
+    fn unhex_leaves_anything_else_alone() {
+        let mut out = Vec::new();
+        assert_eq!(unhex(b"$HEX[613a62]", &mut out), b"a:b");
+        for clear in [&b"$HEX[61626]"[..], b"$HEX[zz]", b"$HEX[6162", b"$hex[6162]", b"abc"] {
+            assert_eq!(unhex(clear, &mut out), clear);
+        }
+    }
+
+    #[test]
+    fn hex_only_when_needed() {
+        let mut out = Vec::new();
+        for (clear, written) in [
+            (&b"password"[..], &b"password"[..]),
+            ("café".as_bytes(), "café".as_bytes()),
+            (b"a:b", b"$HEX[613a62]"),
+            (b"a\tb", b"$HEX[610962]"),
+            (b"caf\xe9", b"$HEX[636166e9]"),
+            (b"$HEX[41]", b"$HEX[244845585b34315d]"),
+        ] {
+            out.clear();
+            write_hex(&mut out, clear);
+            assert_eq!(out, written);
+            assert_eq!(needs_hex(clear), clear != written);
+        }
+    }
+
+    #[test]
+    fn text_for_output() {
+        assert_eq!(to_text(b"caf\xe9", Encoding::Latin1), "café");
+        assert_eq!(to_text(b"\x80", Encoding::Cp1252), "€");
+        assert_eq!(to_text(b"caf\xe9", Encoding::Utf8), "caf\u{FFFD}");
+    }
+}
@@ -0,0 +1,95 @@
+// Writing results as JSON lines, for tools that would rather not parse hash:clear
+
+use std::io::Write;
+
+pub fn write_string(out: &mut Vec<u8>, value: &str) {
+    // Write a quoted and escaped JSON string /*{{{*/
+    out.push(b'"');
+    for c in value.chars() {
+        match c {
+            '"' => out.extend_from_slice(b"\\\""),
+            '\\' => out.extend_from_slice(b"\\\\"),
+            '\n' => out.extend_from_slice(b"\\n"),
+            '\r' => out.extend_from_slice(b"\\r"),
+            '\t' => out.extend_from_slice(b"\\t"),
+            // the rest of the control characters only have the \u form
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => {
+                let mut utf8 = [0_u8; 4];
+                out.extend_from_slice(c.encode_utf8(&mut utf8).as_bytes());
+            }
+        }
+    }
+    out.push(b'"');
+}
+/*}}}*/
+
+pub fn write_field(out: &mut Vec<u8>, name: &str, value: &str) {
+    // Write a "name":"value" pair, with the comma if it isn't the first /*{{{*/
+    if out.last() != Some(&b'{') {
+        out.push(b',');
+    }
+    write_string(out, name);
+    out.push(b':');
+    write_string(out, value);
+}
+/*}}}*/
+
+pub fn write_number(out: &mut Vec<u8>, name: &str, value: impl std::fmt::Display) {
+    // Write a "name":number pair, with the comma if it isn't the first /*{{{*/
+    if out.last() != Some(&b'{') {
+        out.push(b',');
+    }
+    write_string(out, name);
+    write!(out, ":{value}").unwrap();
+}
+/*}}}*/
+
+pub fn write_bool(out: &mut Vec<u8>, name: &str, value: bool) {
+    // Write a "name":true or false pair, with the comma if it isn't the first /*{{{*/
+    if out.last() != Some(&b'{') {
+        out.push(b',');
+    }
+    write_string(out, name);
+    out.extend_from_slice(if value { b":true" } else { b":false" });
+}
+/*}}}*/
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoding::{self, Encoding};
+
+    fn string(value: &str) -> String {
+        let mut out = Vec::new();
+        write_string(&mut out, value);
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn escaping() {
+        assert_eq!(string("password"), r#""password""#);
+        assert_eq!(string(r#"say "hi""#), r#""say \"hi\"""#);
+        assert_eq!(string(r"C:\temp"), r#""C:\\temp""#);
+        assert_eq!(string("a\tb\r\nc"), r#""a\tb\r\nc""#);
+        assert_eq!(string("\u{0}\u{1b}\u{1f} \u{7f}"), "\"\\u0000\\u001b\\u001f \u{7f}\"");
+    }
+
+    #[test]
+    fn non_ascii_clears() {
+        // clears decoded from the wordlist's encoding come out as UTF-8
+        let clear = encoding::to_text(b"caf\xe9 \x80", Encoding::Cp1252);
+        assert_eq!(string(&clear), "\"café €\"");
+        assert_eq!(string("\u{1F600}"), "\"\u{1F600}\"");
+    }
+
+    #[test]
+    fn fields() {
+        let mut out = vec![b'{'];
+        write_field(&mut out, "clear", "a:b");
+        write_number(&mut out, "offset", 1123);
+        write_bool(&mut out, "interrupted", false);
+        out.push(b'}');
+        assert_eq!(out, br#"{"clear":"a:b","offset":1123,"interrupted":false}"#);
+    }
+}
@@ -0,0 +1,278 @@
+// Keyboard walks like qwerty123, 1qaz2wsx and zaq1@WSX
+
+use crate::Generator;
+
+pub struct Layout {
+    // A keyboard's four main rows, unshifted and shifted /*{{{*/
+    rows: [Vec<char>; 4],
+    shifted: [Vec<char>; 4],
+    // Where each row starts, in keys, so that the key below row[c] on the next
+    // row is at c (down and to the right) and c - 1 (down and to the left)
+    offsets: [isize; 4],
+}
+/*}}}*/
+
+fn layout(rows: [&str; 4], shifted: [&str; 4], offsets: [isize; 4]) -> Layout {
+    Layout {
+        rows: rows.map(|r| r.chars().collect()),
+        shifted: shifted.map(|r| r.chars().collect()),
+        offsets,
+    }
+}
+
+impl Layout {
+    pub fn from_name(name: &str) -> Option<Layout> {
+        // The layouts we know about /*{{{*/
+        let layout = match name.to_ascii_lowercase().as_str() {
+            "us" => layout(
+                ["1234567890-=", "qwertyuiop[]", "asdfghjkl;'", "zxcvbnm,./"],
+                ["!@#$%^&*()_+", "QWERTYUIOP{}", "ASDFGHJKL:\"", "ZXCVBNM<>?"],
+                [0, 0, 0, 0],
+            ),
+            "uk" => layout(
+                ["1234567890-=", "qwertyuiop[]", "asdfghjkl;'#", "\\zxcvbnm,./"],
+                ["!\"£$%^&*()_+", "QWERTYUIOP{}", "ASDFGHJKL:@~", "|ZXCVBNM<>?"],
+                [0, 0, 0, -1],
+            ),
+            "de" => layout(
+                ["1234567890ß´", "qwertzuiopü+", "asdfghjklöä#", "<yxcvbnm,.-"],
+                ["!\"§$%&/()=?`", "QWERTZUIOPÜ*", "ASDFGHJKLÖÄ'", ">YXCVBNM;:_"],
+                [0, 0, 0, -1],
+            ),
+            "fr" => layout(
+                ["&é\"'(-è_çà)=", "azertyuiop^$", "qsdfghjklmù*", "<wxcvbn,;:!"],
+                ["1234567890°+", "AZERTYUIOP¨£", "QSDFGHJKLM%µ", ">WXCVBN?./§"],
+                [0, 0, 0, -1],
+            ),
+            _ => return None,
+        };
+        Some(layout)
+    }
+    /*}}}*/
+
+    fn neighbour(&self, key: Key, dir: Dir) -> Option<Key> {
+        // The key next to this one in a direction, if there is one /*{{{*/
+        let (row, col) = key;
+        let (down, across) = match dir {
+            Dir::Left => (0, -1),
+            Dir::Right => (0, 1),
+            Dir::UpLeft => (-1, 0),
+            Dir::UpRight => (-1, 1),
+            Dir::DownLeft => (1, -1),
+            Dir::DownRight => (1, 0),
+        };
+        let row2 = row.checked_add_signed(down).filter(|r| *r < 4)?;
+        let pos = col as isize + self.offsets[row] + across;
+        let col2 = usize::try_from(pos - self.offsets[row2]).ok()?;
+        (col2 < self.rows[row2].len()).then_some((row2, col2))
+    }
+    /*}}}*/
+
+    fn char(&self, key: Key, shifted: bool) -> char {
+        match shifted {
+            true => self.shifted[key.0][key.1],
+            false => self.rows[key.0][key.1],
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Dir {
+    Left,
+    Right,
+    UpLeft,
+    UpRight,
+    DownLeft,
+    DownRight,
+}
+
+const DIRS: [Dir; 6] = [
+    Dir::Left,
+    Dir::Right,
+    Dir::UpLeft,
+    Dir::UpRight,
+    Dir::DownLeft,
+    Dir::DownRight,
+];
+
+type Key = (usize, usize); // row and column
+
+#[derive(Clone, Copy)]
+struct Step {
+    key: Key,
+    shifted: bool,
+    stroke_start: Key,   // where the current straight line started
+    dir: Option<Dir>,    // which way the current line is going, once we know
+    strokes: usize,      // how many straight lines the walk has had
+    shifts: usize,       // how many times shift has been toggled
+}
+
+struct Frame {
+    // A key in the walk, and the moves we've still to try from it /*{{{*/
+    moves: Vec<Step>,
+    next: usize,
+    len: usize, // how long the walk was before this key, to go back to
+}
+/*}}}*/
+
+pub struct Keywalk {
+    layouts: Vec<Layout>,
+    min: usize,
+    max: usize,
+    turns: usize,  // how many times the walk can change direction or jump
+    shifts: usize, // how many times shift can be toggled
+    starts: Vec<(usize, Step)>, // each layout's first keys, still to walk from
+    layout: usize,
+    stack: Vec<Frame>,
+    walk: Vec<u8>,
+}
+
+impl Keywalk {
+    pub fn new(layouts: Vec<Layout>, min: usize, max: usize, turns: usize, shifts: usize) -> Keywalk {
+        // Walk from every key on every layout, shifted or not /*{{{*/
+        let mut starts = Vec::new();
+        for (i, layout) in layouts.iter().enumerate() {
+            for (row, keys) in layout.rows.iter().enumerate() {
+                for col in 0..keys.len() {
+                    for shifted in [false, true] {
+                        if shifted && shifts == 0 {
+                            continue;
+                        }
+                        let step = Step {
+                            key: (row, col),
+                            shifted,
+                            stroke_start: (row, col),
+                            dir: None,
+                            strokes: 1,
+                            shifts: shifted as usize,
+                        };
+                        starts.push((i, step));
+                    }
+                }
+            }
+        }
+        // pop() takes them off the end
+        starts.reverse();
+        Keywalk {
+            layouts,
+            min,
+            max,
+            turns,
+            shifts,
+            starts,
+            layout: 0,
+            stack: Vec::new(),
+            walk: Vec::new(),
+        }
+    }
+    /*}}}*/
+
+    fn moves(&self, step: &Step, depth: usize) -> Vec<Step> {
+        // Where the walk can go next from here /*{{{*/
+        let mut moves = Vec::new();
+        if depth >= self.max {
+            return moves;
+        }
+        let layout = &self.layouts[self.layout];
+        match step.dir {
+            // the second key of a line decides which way it goes
+            None => {
+                for dir in DIRS {
+                    if let Some(key) = layout.neighbour(step.key, dir) {
+                        moves.push(Step { key, dir: Some(dir), ..*step });
+                    }
+                }
+            }
+            Some(dir) => {
+                if let Some(key) = layout.neighbour(step.key, dir) {
+                    moves.push(Step { key, ..*step });
+                }
+                // start a new line next to where this one ended, e.g. qwerty123,
+                // or next to where it started, e.g. 1qaz2wsx
+                if step.strokes > self.turns {
+                    return moves;
+                }
+                let ahead = layout.neighbour(step.key, dir);
+                let mut origins = vec![step.key];
+                if step.stroke_start != step.key {
+                    origins.push(step.stroke_start);
+                }
+                for origin in origins {
+                    for dir in DIRS {
+                        let Some(key) = layout.neighbour(origin, dir) else {
+                            continue;
+                        };
+                        // that's just carrying on
+                        if origin == step.key && Some(key) == ahead {
+                            continue;
+                        }
+                        let new_line = Step {
+                            key,
+                            stroke_start: key,
+                            dir: None,
+                            strokes: step.strokes + 1,
+                            ..*step
+                        };
+                        moves.push(new_line);
+                        // shift can go on or off with a new line, e.g. zaq1@WSX
+                        if step.shifts < self.shifts {
+                            moves.push(Step {
+                                shifted: !step.shifted,
+                                shifts: step.shifts + 1,
+                                ..new_line
+                            });
+                        }
+                    }
+                }
+            }
+        }
+        moves
+    }
+    /*}}}*/
+
+    fn push(&mut self, step: Step, chunk: &mut Vec<u8>) {
+        // Add a key to the walk, writing it out if it's long enough /*{{{*/
+        let len = self.walk.len();
+        let c = self.layouts[self.layout].char(step.key, step.shifted);
+        let mut utf8 = [0_u8; 4];
+        self.walk.extend_from_slice(c.encode_utf8(&mut utf8).as_bytes());
+        let depth = self.stack.len() + 1;
+        if depth >= self.min {
+            chunk.extend_from_slice(&self.walk);
+            chunk.push(10);
+        }
+        let moves = self.moves(&step, depth);
+        self.stack.push(Frame {
+            moves,
+            next: 0,
+            len,
+        });
+    }
+    /*}}}*/
+}
+
+impl Generator for Keywalk {
+    fn fill(&mut self, chunk: &mut Vec<u8>, size: usize) -> bool {
+        // Carry on the depth first walk until the chunk is full /*{{{*/
+        while chunk.len() < size {
+            let Some(frame) = self.stack.last_mut() else {
+                let Some((layout, step)) = self.starts.pop() else {
+                    return false;
+                };
+                self.layout = layout;
+                self.push(step, chunk);
+                continue;
+            };
+            if frame.next == frame.moves.len() {
+                self.walk.truncate(frame.len);
+                self.stack.pop();
+                continue;
+            }
+            let step = frame.moves[frame.next];
+            frame.next += 1;
+            self.push(step, chunk);
+        }
+        !(self.stack.is_empty() && self.starts.is_empty())
+    }
+    /*}}}*/
+}
@@ -3,21 +3,56 @@ extern crate hex;
 extern crate num_cpus;
 extern crate ripline;
 
+// Set by --quiet, when only the results get printed
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+// Diagnostics go to stderr, out of the way of the results on stdout
+macro_rules! info {
+    ($($arg:tt)*) => {
+        if !$crate::QUIET.load(std::sync::atomic::Ordering::Relaxed) {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+mod compressed;
+mod encoding;
+mod json;
+mod keywalk;
+mod markov;
+mod mask;
 mod md4;
+mod patterns;
+mod prince;
+mod rules;
+mod session;
+mod usernames;
 
-use crossbeam_channel::unbounded;
+use compressed::Compression;
+use crossbeam_channel::{bounded, unbounded, RecvTimeoutError};
+use encoding::Encoding;
+use markov::Markov;
+use mask::Mask;
+use rules::Rule;
+use session::Session;
 // Special hasher for already hashed data - NTLM is a hash
 use hash_hasher::HashedMap;
 use hex::FromHex;
 use memmap2::Mmap;
 use ripline::lines::LineIter;
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashSet};
 use std::env;
 use std::error::Error;
-use std::fs::File;
+use std::fs::{self, File, OpenOptions};
 use std::io::{stdout, Read, Seek, SeekFrom, Write};
+use std::ops::{ControlFlow, RangeInclusive};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 use std::thread;
 use std::thread::JoinHandle;
-use std::time::Instant;
+use std::time::{Duration, Instant, SystemTime};
 
 // BSD/macOS and Linux use different uncache calls msync vs fadvise
 #[cfg(target_os = "macos")]
@@ -89,7 +124,7 @@ fn cache_file(file: &mut File, length: usize, block_size: usize, offset: u64) ->
     /*
       let elapsed = (start.elapsed().as_secs() as f64)
                     + (f64::from(start.elapsed().subsec_nanos()) / 1_000_000_000.0);
-      println!("[+] Read {length} bytes in {elapsed:.2} s ({:.2} GB/s)",
+      info!("[+] Read {length} bytes in {elapsed:.2} s ({:.2} GB/s)",
         (length as f64 / elapsed) / 1024.0 / 1024.0 / 1024.0);
       elapsed
     */
@@ -97,10 +132,46 @@ fn cache_file(file: &mut File, length: usize, block_size: usize, offset: u64) ->
 /*}}}*/
 
 #[derive(Clone)]
+struct Account {
+    // The account an input hash was dumped from, if the hashlist told us /*{{{*/
+    domain: Option<String>,
+    username: String,
+    history: Option<u32>, // which _historyN entry this is, if any
+    enabled: Option<bool>, // from the secretsdump (status=...) suffix
+}
+/*}}}*/
+
+impl Account {
+    fn write_name(&self, out: &mut Vec<u8>) {
+        // Write the account name back out the way secretsdump printed it /*{{{*/
+        if let Some(domain) = &self.domain {
+            out.extend_from_slice(domain.as_bytes());
+            out.extend_from_slice(b"\\");
+        }
+        out.extend_from_slice(self.username.as_bytes());
+        if let Some(history) = self.history {
+            write!(out, "_history{history}").unwrap();
+        }
+    }
+    /*}}}*/
+}
+
+// Hashes and the accounts which share each one, empty if we were only given the hash
+type Accounts = HashedMap<[u8; 16], Vec<Account>>;
+
+struct Target {
+    // A hash we're trying to crack, shared between all the threads /*{{{*/
+    accounts: Vec<Account>,
+    // set by whichever thread cracks it first, so it's only reported once
+    cracked: AtomicBool,
+}
+/*}}}*/
+
 struct Hashes {
     // Structuroe to hold our hashlist /*{{{*/
     //hashlist: HashedMap<GenericArray<u8, U16>, i8>,
-    hashlist: HashedMap<[u8; 16], i8>,
+    hashlist: HashedMap<[u8; 16], Target>,
+    found: AtomicUsize, // how many distinct hashes have been cracked
     starts: [bool; 256],
     ends: [bool; 256],
     big: bool,
@@ -108,9 +179,127 @@ struct Hashes {
 }
 /*}}}*/
 
-fn parse_hashes(path: &str) -> Result<Hashes, Box<dyn Error>> {
+#[derive(Clone, Copy, PartialEq)]
+enum HashFormat {
+    // The different hashlist line formats we can auto-detect /*{{{*/
+    Bare,    // 8846f7eaee8fb117ad06bdd830b7586c
+    John,    // $NT$8846f7eaee8fb117ad06bdd830b7586c
+    Potfile, // 8846f7eaee8fb117ad06bdd830b7586c:password (hashcat -m1000 output)
+    User,    // alice:$NT$8846f7eaee8fb117ad06bdd830b7586c or alice:8846...
+    Pwdump,  // CORP\alice:1104:aad3b435b51404eeaad3b435b51404ee:8846...:::
+}
+/*}}}*/
+
+fn parse_nt(field: &str) -> Option<[u8; 16]> {
+    // Parse a bare or john $NT$ tagged hash, in any case /*{{{*/
+    let field = field.trim();
+    let field = field.strip_prefix("$NT$").unwrap_or(field);
+    <[u8; 16]>::from_hex(field).ok()
+}
+/*}}}*/
+
+fn parse_account(user: &str) -> Account {
+    // Split a DOMAIN\user_historyN name into its parts /*{{{*/
+    let (domain, user) = match user.split_once('\\') {
+        Some((domain, user)) => (Some(domain.to_string()), user),
+        None => (None, user),
+    };
+    // secretsdump -history appends _historyN to the username for old hashes
+    let (username, history) = match user.rsplit_once("_history") {
+        Some((name, n)) if !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()) => {
+            (name, n.parse::<u32>().ok())
+        }
+        _ => (user, None),
+    };
+    Account {
+        domain,
+        username: username.to_string(),
+        history,
+        enabled: None,
+    }
+}
+/*}}}*/
+
+fn parse_pwdump(line: &str) -> Option<([u8; 16], Account)> {
+    // Parse a pwdump/secretsdump DOMAIN\user:rid:lmhash:nthash::: line /*{{{*/
+    let mut fields = line.splitn(7, ':');
+    let user = fields.next()?;
+    let _rid = fields.next()?.parse::<u32>().ok()?;
+    let _lmhash = fields.next()?;
+    let raw_hash = <[u8; 16]>::from_hex(fields.next()?).ok()?;
+    // anything after the ::: is extra secretsdump info e.g. " (status=Enabled)"
+    let extra = fields.nth(2).unwrap_or("");
+    let mut account = parse_account(user);
+    if extra.contains("(status=Enabled)") {
+        account.enabled = Some(true);
+    } else if extra.contains("(status=Disabled)") {
+        account.enabled = Some(false);
+    }
+    Some((raw_hash, account))
+}
+/*}}}*/
+
+fn parse_hash_line(line: &str) -> Option<(HashFormat, [u8; 16], Option<Account>)> {
+    // Work out which format a hashlist line is in and pull the hash out /*{{{*/
+    if let Some(raw_hash) = parse_nt(line) {
+        let format = match line.trim_start().starts_with("$NT$") {
+            true => HashFormat::John,
+            false => HashFormat::Bare,
+        };
+        return Some((format, raw_hash, None));
+    }
+    let (first, rest) = line.split_once(':')?;
+    // hashcat potfile lines, the clear can contain colons so only split once
+    if let Some(raw_hash) = parse_nt(first) {
+        return Some((HashFormat::Potfile, raw_hash, None));
+    }
+    if let Some((raw_hash, account)) = parse_pwdump(line) {
+        return Some((HashFormat::Pwdump, raw_hash, Some(account)));
+    }
+    // john's user:$NT$hash, possibly with more fields tacked on the end
+    let raw_hash = parse_nt(rest.split(':').next()?)?;
+    Some((HashFormat::User, raw_hash, Some(parse_account(first))))
+}
+/*}}}*/
+
+// Hashes we've already cracked and their clears, from the potfile
+type Potfile = HashedMap<[u8; 16], Vec<u8>>;
+
+fn load_potfile(path: &str) -> Result<Potfile, Box<dyn Error>> {
+    // Read the hash:clear lines of previous runs, if there are any /*{{{*/
+    let mut potfile = Potfile::default();
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(potfile),
+        Err(e) => return Err(e.into()),
+    };
+    if file.metadata()?.len() == 0 {
+        return Ok(potfile); // can't mmap an empty file
+    }
+    let potin = unsafe { Mmap::map(&file)? };
+    let mut unhexed = Vec::new();
+    for l in LineIter::new(b'\n', &potin) {
+        // only strip the line ending, spaces at the end are part of the clear
+        let l = l.strip_suffix(b"\n").unwrap_or(l);
+        let l = l.strip_suffix(b"\r").unwrap_or(l);
+        let Some(colon) = l.iter().position(|c| *c == b':') else {
+            continue;
+        };
+        let Some(raw_hash) = parse_nt(&String::from_utf8_lossy(&l[..colon])) else {
+            continue;
+        };
+        let clear = &l[colon + 1..];
+        let clear = encoding::unhex(clear, &mut unhexed);
+        potfile.insert(raw_hash, clear.to_vec());
+    }
+    Ok(potfile)
+}
+/*}}}*/
+
+fn parse_hashes(path: &str, potfile: &Potfile) -> Result<(Hashes, Accounts), Box<dyn Error>> {
     // Turn input hashes into required data structures /*{{{*/
-    let file = File::open(&path)?;
+    // Anything already in the potfile is returned separately, not to be cracked
+    let file = File::open(path)?;
     let hashin = unsafe { Mmap::map(&file)? };
     let iter = LineIter::new(b'\n', &hashin);
 
@@ -123,17 +312,77 @@ fn parse_hashes(path: &str) -> Result<Hashes, Box<dyn Error>> {
     // Since searching these hashes is the biggest cost of this whole thing
     // we use a HashMap for 0(1)~ performance
     //let hashlist: HashedMap<GenericArray<u8, U16>, _> = iter
-    let hashlist: HashedMap<[u8; 16], _> = iter
-        .map(|l| {
-            let raw_hash = <[u8; 16]>::from_hex(&l[0..l.len() - 1]).unwrap();
-            //let hashes: GenericArray<u8, U16> = *GenericArray::from_slice(&raw_hash);
-            // Store the first and last byte of the hash in an array
-            // these are used for a fast checks to avoid a more expensive HashMap lookup
-            starts[raw_hash[0] as usize] = true;
-            ends[raw_hash[15] as usize] = true;
-            (raw_hash, 0)
-        })
-        .collect();
+    let mut hashlist = Accounts::default();
+    let mut potted = Accounts::default();
+    let mut formats = [0_usize; 5]; // how many lines of each HashFormat we saw
+    let mut bad_lines = 0;
+    for (lineno, l) in iter.enumerate() {
+        let line = String::from_utf8_lossy(l);
+        // trim_end also takes care of CRLF line endings
+        let line = line.trim_end();
+        if line.is_empty() {
+            continue;
+        }
+        let (format, raw_hash, account) = match parse_hash_line(line) {
+            Some(parsed) => parsed,
+            None => {
+                // report the first few bad lines, but don't drown the user
+                bad_lines += 1;
+                if bad_lines <= 10 {
+                    info!("[!] Couldn't parse line {} of {path}: {line}", lineno + 1);
+                }
+                continue;
+            }
+        };
+        formats[format as usize] += 1;
+        // no need to crack what a previous run already did
+        if potfile.contains_key(&raw_hash) {
+            let accounts = potted.entry(raw_hash).or_default();
+            if let Some(account) = account {
+                accounts.push(account);
+            }
+            continue;
+        }
+        //let hashes: GenericArray<u8, U16> = *GenericArray::from_slice(&raw_hash);
+        // Store the first and last byte of the hash in an array
+        // these are used for a fast checks to avoid a more expensive HashMap lookup
+        starts[raw_hash[0] as usize] = true;
+        ends[raw_hash[15] as usize] = true;
+        let accounts = hashlist.entry(raw_hash).or_default();
+        if let Some(account) = account {
+            accounts.push(account);
+        }
+    }
+
+    if bad_lines > 0 {
+        info!("[!] Skipped {bad_lines} lines of {path} that weren't NT hashes");
+    }
+    if hashlist.is_empty() && potted.is_empty() {
+        return Err(format!("No NT hashes found in {path}").into());
+    }
+    if !potted.is_empty() {
+        info!("[+] {} hashes were already in the potfile", potted.len());
+    }
+    if formats[HashFormat::Bare as usize] != formats.iter().sum() {
+        info!(
+            "[+] Hash formats found: {} bare, {} john, {} potfile, {} user:hash, {} pwdump",
+            formats[HashFormat::Bare as usize],
+            formats[HashFormat::John as usize],
+            formats[HashFormat::Potfile as usize],
+            formats[HashFormat::User as usize],
+            formats[HashFormat::Pwdump as usize]
+        );
+    }
+    let accounts: Vec<&Account> = hashlist.values().flatten().collect();
+    if !accounts.is_empty() {
+        let history = accounts.iter().filter(|a| a.history.is_some()).count();
+        let disabled = accounts.iter().filter(|a| a.enabled == Some(false)).count();
+        info!(
+            "[+] Loaded {} hashes for {} accounts ({history} history, {disabled} disabled)",
+            hashlist.len(),
+            accounts.len()
+        );
+    }
 
     // For big input hash lists we want to skip the fast byte check below
     let big = match hashlist.len() {
@@ -144,13 +393,25 @@ fn parse_hashes(path: &str) -> Result<Hashes, Box<dyn Error>> {
     // This decides when a thread should notify the main that it's cracked stuff
     let updatethresh = if big { 10 } else { 1 };
 
-    Ok(Hashes {
-        hashlist,
-        starts,
-        ends,
-        big,
-        updatethresh,
-    })
+    let hashlist = hashlist
+        .into_iter()
+        .map(|(hash, accounts)| {
+            let cracked = AtomicBool::new(false);
+            (hash, Target { accounts, cracked })
+        })
+        .collect();
+
+    Ok((
+        Hashes {
+            hashlist,
+            found: AtomicUsize::new(0),
+            starts,
+            ends,
+            big,
+            updatethresh,
+        },
+        potted,
+    ))
 }
 /*}}}*/
 
@@ -160,8 +421,10 @@ struct Wordlist {
     mmap: Mmap,
     cache_point: usize,
     length: usize,
+    #[allow(dead_code)] // only read by the debugging stats in read_wordlist
     pages: usize,
     cache_size: usize,
+    utf16: bool, // is the wordlist already UTF-16LE encoded
 }
 /*}}}*/
 
@@ -169,42 +432,48 @@ fn initialise_wordlist(
     path: &str,
     cache_size: usize,
     block_size: usize,
+    utf16: bool,
+    start: usize,
 ) -> Result<Wordlist, Box<dyn Error>> {
-    // Read and cache the start of the wordlist /*{{{*/
-    let mut wordlist_file = File::open(&path)?;
+    // Read and cache the start of the wordlist, or from where we're starting /*{{{*/
+    let mut wordlist_file = File::open(path)?;
     let wordlist_mmap = unsafe { Mmap::map(&wordlist_file)? };
+    if start > wordlist_mmap.len() {
+        return Err(format!("{path} is shorter than the session says, has it changed?").into());
+    }
 
     let page_size = page_size::get();
     let wordlist_length = wordlist_mmap.len();
-    let wordlist_pages = (wordlist_length + page_size - 1) / page_size;
+    let wordlist_pages = wordlist_length.div_ceil(page_size);
     let cache_point;
 
     let mut answer = vec![0u8; wordlist_pages];
     mincore_check(&wordlist_mmap, wordlist_length, &mut answer);
     let mut percent_cached: f64 = gen_stats(&answer, wordlist_pages);
-    println!("[+] Wordlist is {wordlist_length} bytes and {wordlist_pages} pages, currently {percent_cached:.2}% cached");
+    info!("[+] Wordlist is {wordlist_length} bytes and {wordlist_pages} pages, currently {percent_cached:.2}% cached");
 
     if percent_cached < 97.0 {
-        if wordlist_length > cache_size {
-            let _elapsed_time = cache_file(&mut wordlist_file, cache_size, block_size, 0);
+        if wordlist_length - start > cache_size {
+            let _elapsed_time = cache_file(&mut wordlist_file, cache_size, block_size, start as u64);
             mincore_check(&wordlist_mmap, wordlist_length, &mut answer);
             percent_cached = gen_stats(&answer, wordlist_pages);
             assert!(wordlist_length <= f64::MAX as usize); // safe f64 conversion
             if percent_cached >= (wordlist_length / cache_size) as f64 {
-                println!("[*] Successfully cached first part of wordlist");
+                info!("[*] Successfully cached first part of wordlist");
             }
-            cache_point = cache_size;
+            cache_point = start + cache_size;
         } else {
-            let _elapsed_time = cache_file(&mut wordlist_file, wordlist_length, block_size, 0);
+            let _elapsed_time =
+                cache_file(&mut wordlist_file, wordlist_length - start, block_size, start as u64);
             mincore_check(&wordlist_mmap, wordlist_length, &mut answer);
             percent_cached = gen_stats(&answer, wordlist_pages);
             if percent_cached >= 95.0 {
-                println!("Successfully cached wordlist");
+                info!("Successfully cached wordlist");
             }
             cache_point = wordlist_length;
         }
     } else {
-        println!("Wordlist already cached");
+        info!("Wordlist already cached");
         cache_point = wordlist_length;
     }
 
@@ -215,65 +484,690 @@ fn initialise_wordlist(
         length: wordlist_length,
         pages: wordlist_pages,
         cache_size,
+        utf16,
     })
 }
 /*}}}*/
 
+fn is_stream(path: &str) -> bool {
+    // Is the wordlist stdin or a pipe, which we can't mmap or cache /*{{{*/
+    path == "-" || std::fs::metadata(path).is_ok_and(|m| !m.is_file())
+}
+/*}}}*/
+
+fn open_stream(path: &str) -> Result<Box<dyn Read + Send>, Box<dyn Error>> {
+    // Open stdin or a FIFO to stream the wordlist from /*{{{*/
+    info!("[+] Streaming the wordlist from {}", if path == "-" { "stdin" } else { path });
+    match path {
+        "-" => Ok(Box::new(std::io::stdin())),
+        _ => Ok(Box::new(File::open(path)?)),
+    }
+}
+/*}}}*/
+
 struct Workers {
     // Structure to hold our thread worker info /*{{{*/
     threadnum: usize,
-    threadhand: Vec<JoinHandle<()>>,
-    tx: crossbeam_channel::Sender<Option<Vec<u8>>>,
+    threadhand: Vec<JoinHandle<Vec<Vec<u8>>>>, // each thread hands back its loopback clears
+    max_queued: usize, // how many jobs we let queue up for the threads
+    tx: crossbeam_channel::Sender<Chunk>,
     //rx: crossbeam_channel::Receiver<Option<Vec<u8>>>,
     //tx2: crossbeam_channel::Sender<Stats>,
     rx2: crossbeam_channel::Receiver<Stats>,
+    cracked_from: Arc<Vec<AtomicUsize>>, // how many each wordlist has cracked
+    progress: Arc<Mutex<Progress>>,
+    saver: Option<Saver>, // None unless we're reading wordlists with a session
+    status: Status,
+    checked: Cell<Instant>, // when throttle() last checked in with the threads
+    done: Cell<Stats>, // what was done before the stats the reading is keeping now
+    split: Vec<(Arc<Mask>, u64)>, // the hybrid masks and keyspaces, if each word's worth sharing out
+}
+/*}}}*/
+
+impl Workers {
+    fn send_words(&self, chunk: Vec<u8>, wordlist: usize, offset: u64) -> Result<(), Box<dyn Error>> {
+        // Send a chunk of a wordlist to the threads, keeping track of it until it's done /*{{{*/
+        if !self.split.is_empty() {
+            return self.send_hybrid(&chunk, wordlist, offset);
+        }
+        let mut progress = self.progress.lock().unwrap();
+        progress.pending.insert((wordlist, offset), 1);
+        progress.sent = (wordlist, offset + chunk.len() as u64);
+        drop(progress);
+        self.tx.send(Some(Job::Words(chunk, wordlist, offset)))?;
+        Ok(())
+    }
+    /*}}}*/
+
+    fn send_hybrid(&self, chunk: &[u8], wordlist: usize, offset: u64) -> Result<(), Box<dyn Error>> {
+        // Share out each word's mask candidates, so even a few words keep all the threads busy /*{{{*/
+        let mut jobs = Vec::new();
+        let mut progress = self.progress.lock().unwrap();
+        for line in chunk.split(|c| *c == 10_u8).filter(|l| !l.is_empty()) {
+            let word: Arc<[u8]> = Arc::from(line);
+            let at = offset + (line.as_ptr() as usize - chunk.as_ptr() as usize) as u64;
+            let queued = jobs.len();
+            for (mask, keyspace) in &self.split {
+                let slice = (keyspace / self.threadnum as u64).max(MIN_HYBRID_SLICE);
+                let mut start = 0;
+                while start < *keyspace {
+                    let end = start.saturating_add(slice).min(*keyspace);
+                    jobs.push(Job::Hybrid(Arc::clone(&word), wordlist, at, Arc::clone(mask), start, end));
+                    start = end;
+                }
+            }
+            // the word's done once all of its slices are
+            progress.pending.insert((wordlist, at), jobs.len() - queued);
+        }
+        progress.sent = (wordlist, offset + chunk.len() as u64);
+        drop(progress);
+        for job in jobs {
+            self.tx.send(Some(job))?;
+        }
+        Ok(())
+    }
+    /*}}}*/
+
+    fn totals(&self, stats: &Stats) -> Stats {
+        // Everything done so far, given the stats the reading is keeping now
+        let mut totals = self.done.get();
+        totals.add(stats);
+        totals
+    }
+
+    fn save_session(&self, stats: &Stats) {
+        // Save the session if we have one and it's been a while /*{{{*/
+        let Some(saver) = self.saver.as_ref().filter(|s| s.saved.get().elapsed() >= SAVE_EVERY) else {
+            return;
+        };
+        if let Err(e) = saver.save(&self.progress, &self.totals(stats)) {
+            info!("[!] Failed to save the session: {e}");
+        }
+    }
+    /*}}}*/
+
+    fn throttle(&self, hashes: &Hashes, stats: &mut Stats) -> bool {
+        // Don't get too far ahead of the threads, true once everything's cracked or we're interrupted /*{{{*/
+        // a slow reader never fills the queue, so check in now and then anyway
+        // to keep the status line and session going
+        let due = self.checked.get().elapsed() >= CHECK_EVERY;
+        if !due && self.tx.len() < self.max_queued && !interrupted() {
+            return false;
+        }
+        self.checked.set(Instant::now());
+        wait_for_workers(self, hashes, stats)
+    }
+    /*}}}*/
+
+    fn show_status(&self, stats: &Stats) {
+        // Print the status line if it's been asked for or it's time to /*{{{*/
+        let status = &self.status;
+        let due = status.every.is_some_and(|every| status.last.get().0.elapsed() >= every)
+            && !QUIET.load(Ordering::Relaxed);
+        if STATUS_WANTED.swap(false, Ordering::Relaxed) || due {
+            let position = self.progress.lock().unwrap().checkpoint();
+            status.show(position, &self.totals(stats));
+        }
+    }
+    /*}}}*/
+}
+
+#[derive(Default)]
+struct Progress {
+    // Which wordlist chunks the threads are still on, so we know where it's safe to restart /*{{{*/
+    sent: (usize, u64),                 // the wordlist and offset the last chunk sent ended at
+    pending: BTreeMap<(usize, u64), usize>, // where each unfinished chunk starts, and its jobs left
+}
+/*}}}*/
+
+impl Progress {
+    fn checkpoint(&self) -> (usize, u64) {
+        // Everything before the first unfinished chunk is done
+        self.pending.first_key_value().map_or(self.sent, |(origin, _)| *origin)
+    }
+
+    fn finished(&mut self, origin: (usize, u64)) {
+        // One of a chunk's jobs is done, the chunk is once they all are
+        if let Some(jobs) = self.pending.get_mut(&origin) {
+            *jobs -= 1;
+            if *jobs == 0 {
+                self.pending.remove(&origin);
+            }
+        }
+    }
+}
+
+struct Saver {
+    // Saves the session every so often while the wordlists are read /*{{{*/
+    path: String,
+    session: RefCell<Session>,
+    saved: Cell<Instant>,
+    started: (Instant, f64), // when this run started, and the seconds the session had before that
+}
+/*}}}*/
+
+impl Saver {
+    fn save(&self, progress: &Mutex<Progress>, totals: &Stats) -> Result<(), Box<dyn Error>> {
+        // Save where we're up to now, and everything done to get here /*{{{*/
+        let mut session = self.session.borrow_mut();
+        session.stats = *totals;
+        session.elapsed = self.started.1 + self.started.0.elapsed().as_secs_f64();
+        session.position = progress.lock().unwrap().checkpoint();
+        self.saved.set(Instant::now());
+        session.save(&self.path)
+    }
+    /*}}}*/
+}
+
+struct Status {
+    // What the status line is worked out from /*{{{*/
+    hashes: Arc<Hashes>,
+    every: Option<Duration>, // how often to show it, None for only on SIGUSR1
+    sizes: Vec<u64>,         // each wordlist's size, empty if we can't know them all
+    started: (Instant, f64), // when we started, and how far through the wordlists we were
+    compressed: Cell<Option<(u64, u64)>>, // bytes read & decompressed, if the wordlist is compressed
+    last: Cell<(Instant, usize, usize)>, // when it was last shown, and the hashed & kB read then
+}
+/*}}}*/
+
+impl Status {
+    fn new(hashes: &Arc<Hashes>, every: Option<Duration>) -> Status {
+        Status {
+            hashes: Arc::clone(hashes),
+            every,
+            sizes: Vec::new(),
+            started: (Instant::now(), 0.0),
+            compressed: Cell::new(None),
+            last: Cell::new((Instant::now(), 0, 0)),
+        }
+    }
+
+    fn fraction(&self, (wordlist, offset): (usize, u64)) -> Option<f64> {
+        // How far through all the wordlists we are, if we know their sizes /*{{{*/
+        let total: u64 = self.sizes.iter().sum();
+        if total == 0 {
+            return None;
+        }
+        let before: u64 = self.sizes.iter().take(wordlist).sum();
+        // a compressed wordlist's offsets are after decompression, so scale
+        // them down by how well it's compressed so far
+        let current = match self.compressed.get() {
+            Some((read, decompressed)) => (offset as f64 * read as f64 / decompressed.max(1) as f64) as u64,
+            None => offset,
+        };
+        let current = current.min(self.sizes.get(wordlist).copied().unwrap_or(0));
+        Some((before + current) as f64 / total as f64)
+    }
+    /*}}}*/
+
+    fn show(&self, position: (usize, u64), totals: &Stats) {
+        // Write the status line to stderr, out of the way of the results /*{{{*/
+        let (last, last_hashed, last_kbs) = self.last.get();
+        let now = Instant::now();
+        let secs = now.duration_since(last).as_secs_f64().max(0.001);
+        let mut line = String::from("[*] Status:");
+        let fraction = self.fraction(position);
+        if let Some(fraction) = fraction {
+            line += &format!(" {:.2}% of {} wordlists,", fraction * 100.0, self.sizes.len());
+        }
+        line += &format!(
+            " {:.2} kH/s, {:.2} MB/s, Cracked: {}/{}, Waits: {}",
+            totals.hashed.saturating_sub(last_hashed) as f64 / secs / 1024.0,
+            totals.kbs.saturating_sub(last_kbs) as f64 / secs / 1024.0,
+            self.hashes.found.load(Ordering::Relaxed),
+            self.hashes.hashlist.len(),
+            totals.waits
+        );
+        // go on the average speed since we started, the current one jumps about
+        let (started, start) = self.started;
+        if let Some(fraction) = fraction.filter(|f| *f > start) {
+            let left = started.elapsed().as_secs_f64() / (fraction - start) * (1.0 - fraction);
+            line += &format!(", ETA: {}", format_duration(left as u64));
+        }
+        eprintln!("{line}");
+        self.last.set((now, totals.hashed, totals.kbs));
+    }
+    /*}}}*/
+}
+
+fn format_duration(secs: u64) -> String {
+    // Like 2d 03h, 1h 02m or 5m 06s /*{{{*/
+    let (days, hours, mins) = (secs / 86400, secs / 3600 % 24, secs / 60 % 60);
+    match (days, hours, mins) {
+        (0, 0, 0) => format!("{secs}s"),
+        (0, 0, _) => format!("{mins}m {:02}s", secs % 60),
+        (0, _, _) => format!("{hours}h {mins:02}m"),
+        _ => format!("{days}d {hours:02}h"),
+    }
+}
+/*}}}*/
+
+// How often to save the session while reading wordlists
+const SAVE_EVERY: Duration = Duration::from_secs(10);
+// How often to collect the threads' stats even when they're keeping up
+const CHECK_EVERY: Duration = Duration::from_millis(100);
+
+// Set by SIGINT or SIGTERM, everything winds down and saves what it can
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+// Set by SIGUSR1, to show the status line now
+static STATUS_WANTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_signal(_: libc::c_int) {
+    // Only set a flag, a second signal means don't wait /*{{{*/
+    if INTERRUPTED.swap(true, Ordering::Relaxed) {
+        unsafe { libc::_exit(2) };
+    }
+}
+/*}}}*/
+
+extern "C" fn on_status_signal(_: libc::c_int) {
+    STATUS_WANTED.store(true, Ordering::Relaxed);
+}
+
+fn catch_signals() {
+    // Stop cleanly on ^C or a kill, instead of losing what the threads have buffered /*{{{*/
+    let handler = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    unsafe {
+        libc::signal(libc::SIGINT, handler);
+        libc::signal(libc::SIGTERM, handler);
+        let handler = on_status_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        libc::signal(libc::SIGUSR1, handler);
+    }
 }
 /*}}}*/
 
-#[derive(Clone, Copy)] // needed to send via channels between thread and main
+fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::Relaxed)
+}
+
+#[derive(Clone, Copy, Default)] // needed to send via channels between thread and main
 struct Stats {
     // Structure to hold counters from the threads /*{{{*/
     cracked: usize,
     hashed: usize,
     waits: usize,
     kbs: usize,
+    compressed_kbs: usize, // compressed bytes read, when the wordlist is compressed
+}
+/*}}}*/
+
+impl Stats {
+    fn add(&mut self, other: &Stats) {
+        self.cracked += other.cracked;
+        self.hashed += other.hashed;
+        self.waits += other.waits;
+        self.kbs += other.kbs;
+        self.compressed_kbs += other.compressed_kbs;
+    }
+}
+
+fn write_cracked(out: &mut Vec<u8>, account: Option<&Account>, hash: &[u8; 16], clear: &[u8]) {
+    // Add a cracked [user:]hash:clear line to a thread's output buffer /*{{{*/
+    if let Some(account) = account {
+        account.write_name(out);
+        out.extend_from_slice(&[58]); // colon
+    }
+    //writing each character is faster than doing it in one go
+    for x in hash {
+        write!(out, "{:02x}", x).unwrap();
+    }
+    // extend_from_slice is faster than push
+    out.extend_from_slice(&[58]); // colon
+    // don't let colons, newlines or binary break whatever reads this
+    encoding::write_hex(out, clear);
+    out.extend_from_slice(&[10]); // newline
+}
+/*}}}*/
+
+fn write_json(
+    out: &mut Vec<u8>,
+    account: Option<&Account>,
+    hash: &[u8; 16],
+    clear: &str,
+    raw: &[u8],
+    from: Option<(&str, u64)>,
+) {
+    // Add a cracked hash to a thread's output buffer as a line of JSON /*{{{*/
+    out.push(b'{');
+    json::write_field(out, "type", "crack");
+    json::write_field(out, "hash", &hex::encode(hash));
+    json::write_field(out, "clear", clear);
+    // the bytes hash:clear would have printed, in case they aren't valid text
+    json::write_field(out, "clear_hex", &hex::encode(raw));
+    if let Some(account) = account {
+        json::write_field(out, "username", &account.username);
+        if let Some(domain) = &account.domain {
+            json::write_field(out, "domain", domain);
+        }
+        if let Some(history) = account.history {
+            json::write_number(out, "history", history);
+        }
+    }
+    if let Some((wordlist, offset)) = from {
+        json::write_field(out, "wordlist", wordlist);
+        json::write_number(out, "offset", offset);
+    }
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default();
+    json::write_number(out, "timestamp", now.as_secs());
+    out.extend_from_slice(b"}\n");
+}
+/*}}}*/
+
+struct Cracker {
+    // Per thread state for checking candidates and reporting what cracked /*{{{*/
+    hashes: Arc<Hashes>,
+    encoding: Encoding,
+    out: Vec<u8>,
+    outfile: Option<File>, // where the results go instead of stdout
+    potfile: Option<File>,
+    pot: Vec<u8>, // hash:clear lines waiting to go to the potfile
+    loopback: Option<Vec<Vec<u8>>>, // what we cracked, kept if we're going to loop it back
+    wordlist: usize,                // which wordlist we're working through
+    offset: u64,                    // where in it the word we're on starts
+    wordlists: Arc<Vec<String>>,    // their paths, for the JSON output
+    json: bool,
+    cracked_from: Arc<Vec<AtomicUsize>>, // how many each wordlist has cracked
+    stats: Stats,
+    tx2: crossbeam_channel::Sender<Stats>,
+}
+/*}}}*/
+
+impl Cracker {
+    fn new(
+        hashes: &Arc<Hashes>,
+        options: &Options,
+        tx2: crossbeam_channel::Sender<Stats>,
+    ) -> Result<Cracker, Box<dyn Error>> {
+        // Set up a thread's cracker, with its own handle on the potfile /*{{{*/
+        let potfile = match &options.potfile_path {
+            Some(path) => Some(OpenOptions::new().create(true).append(true).open(path)?),
+            None => None,
+        };
+        let outfile = match &options.outfile_path {
+            Some(path) => Some(OpenOptions::new().create(true).append(true).open(path)?),
+            None => None,
+        };
+        Ok(Cracker {
+            hashes: Arc::clone(hashes),
+            encoding: options.encoding,
+            // Pre-allocate to reduce alloc overhead
+            out: Vec::with_capacity(8192),
+            outfile,
+            potfile,
+            pot: Vec::with_capacity(8192),
+            loopback: options.loopback.then(Vec::new),
+            wordlist: NO_WORDLIST,
+            offset: 0,
+            wordlists: Arc::new(options.wordlists.clone()),
+            json: options.json,
+            cracked_from: Arc::default(),
+            stats: Stats::default(),
+            tx2,
+        })
+    }
+    /*}}}*/
+
+    fn flush(&mut self) {
+        // Write out our buffered results and potfile lines /*{{{*/
+        // Only whole lines are buffered, and they go out in one write so the
+        // threads' results never get mixed up with each other
+        match &mut self.outfile {
+            Some(outfile) => outfile.write_all(&self.out).unwrap(),
+            None => stdout().lock().write_all(&self.out).unwrap(),
+        }
+        self.out.clear();
+        if let Some(potfile) = &mut self.potfile {
+            // one write per buffer, the potfile is opened in append mode so the
+            // threads don't write over each other
+            potfile.write_all(&self.pot).unwrap();
+        }
+        self.pot.clear();
+    }
+    /*}}}*/
+
+    fn try_clear(&mut self, clear: &[u8], utf16: &mut [u8]) {
+        // Encode a candidate to UTF-16LE and check it /*{{{*/
+        // skip clears that aren't valid in the input encoding
+        if let Some(len) = encoding::to_utf16le(clear, self.encoding, utf16) {
+            self.check(&utf16[..len], clear);
+        }
+    }
+    /*}}}*/
+
+    fn try_hybrid(
+        &mut self,
+        word: &[u8],
+        (mask, start, end): (&Mask, u64, u64),
+        append: bool,
+        mangled: &mut Vec<u8>,
+        utf16: &mut [u8],
+    ) -> ControlFlow<()> {
+        // Stick a slice of a mask's candidates on a word, breaking off if we're interrupted /*{{{*/
+        // a big mask takes a while, so don't wait for the next word to stop
+        mask.for_each(start, end, |candidate| {
+            if interrupted() {
+                return ControlFlow::Break(());
+            }
+            mangled.clear();
+            if append {
+                mangled.extend_from_slice(word);
+                mangled.extend_from_slice(candidate);
+            } else {
+                mangled.extend_from_slice(candidate);
+                mangled.extend_from_slice(word);
+            }
+            self.try_clear(mangled, utf16);
+            ControlFlow::Continue(())
+        })
+    }
+    /*}}}*/
+
+    fn check(&mut self, utf16: &[u8], clear: &[u8]) {
+        // Hash an encoded candidate and see if it's one we're after /*{{{*/
+        self.stats.hashed += 1;
+        // doing this single Md4 digest is faster than
+        // multiple updates() + finalize()
+        let mut md = md4::MD4::new();
+        md.digest(utf16);
+        //md.digest(&utf16);
+        let hash = md.get_hash();
+
+        if !self.hashes.big {
+            // for small hashlists, can we get away with this cheaper check
+            if !self.hashes.starts[hash[0] as usize] || !self.hashes.ends[hash[15] as usize] {
+                return;
+            }
+        }
+
+        // check if the generated hash is in our input hash list
+        if self.hashes.hashlist.contains_key(&hash) {
+            // report() needs self, so take our own handle on the hashlist, it's
+            // only on a hit so the refcount isn't touched for every candidate
+            let hashes = Arc::clone(&self.hashes);
+            self.report(&hash, &hashes.hashlist[&hash], clear);
+        }
+    }
+    /*}}}*/
+
+    fn try_target(&mut self, clear: &[u8], utf16: &mut [u8], hash: &[u8; 16], target: &Target) {
+        // Check a candidate against just the one hash it was made for /*{{{*/
+        let Some(len) = encoding::to_utf16le(clear, self.encoding, utf16) else {
+            return;
+        };
+        self.stats.hashed += 1;
+        let mut md = md4::MD4::new();
+        md.digest(&utf16[..len]);
+        if md.get_hash() == *hash {
+            self.report(hash, target, clear);
+        }
+    }
+    /*}}}*/
+
+    fn report(&mut self, hash: &[u8; 16], target: &Target, clear: &[u8]) {
+        // Print, potfile & count a crack, unless another thread beat us to it /*{{{*/
+        // only the first thread to find it gets to report it, so
+        // duplicates in the wordlist aren't printed again
+        if target.cracked.swap(true, Ordering::Relaxed) {
+            return;
+        }
+        self.hashes.found.fetch_add(1, Ordering::Relaxed);
+        self.stats.cracked += 1;
+        let accounts = &target.accounts;
+        // UTF-16LE wordlists need turning back into something printable
+        let decoded;
+        let clear = match self.encoding {
+            Encoding::Utf16Le => {
+                decoded = encoding::utf16le_to_utf8(clear);
+                &decoded
+            }
+            _ => clear,
+        };
+        if self.json {
+            // the UTF-16LE has already been decoded to UTF-8
+            let text = match self.encoding {
+                Encoding::Utf16Le => encoding::to_text(clear, Encoding::Utf8),
+                encoding => encoding::to_text(clear, encoding),
+            };
+            let from = self
+                .wordlists
+                .get(self.wordlist)
+                .map(|path| (path.as_str(), self.offset));
+            if accounts.is_empty() {
+                write_json(&mut self.out, None, hash, &text, clear, from);
+            }
+            for account in accounts {
+                write_json(&mut self.out, Some(account), hash, &text, clear, from);
+            }
+        } else {
+            // print user:hash:clear for every account using
+            // this hash, or just hash:clear if we have no users
+            if accounts.is_empty() {
+                write_cracked(&mut self.out, None, hash, clear);
+            }
+            for account in accounts {
+                write_cracked(&mut self.out, Some(account), hash, clear);
+            }
+        }
+        if self.potfile.is_some() {
+            write_cracked(&mut self.pot, None, hash, clear);
+        }
+        if let Some(loopback) = &mut self.loopback {
+            loopback.push(clear.to_vec());
+        }
+        if let Some(count) = self.cracked_from.get(self.wordlist) {
+            count.fetch_add(1, Ordering::Relaxed);
+        }
+        // check if our output buffer should be flushed
+        if self.out.len() >= 8192 {
+            // make sure this comparison aligns with capacity
+            self.flush();
+        }
+        // update the main process on progress
+        if self.stats.cracked == self.hashes.updatethresh {
+            // the waits get sent by the thread loop
+            self.tx2.send(Stats { waits: 0, ..self.stats }).unwrap();
+            self.stats.cracked = 0;
+            self.stats.hashed = 0;
+        }
+    }
+    /*}}}*/
+}
+
+enum Job {
+    // The work we send to the threads /*{{{*/
+    Words(Vec<u8>, usize, u64), // a newline separated chunk, and which wordlist & offset it's from
+    Mask(Arc<Mask>, u64, u64), // a start..end slice of a mask's keyspace
+    // a word, its wordlist & offset, and a slice of a mask to stick on it
+    Hybrid(Arc<[u8]>, usize, u64, Arc<Mask>, u64, u64),
+    Markov(Arc<Markov>, usize, u64, u64), // a slice of one length's Markov keyspace
+}
+/*}}}*/
+
+// The wordlist of Words jobs that are made up rather than read, like loopback's
+const NO_WORDLIST: usize = usize::MAX;
+
+// The fewest mask candidates worth giving a hybrid word's slice its own job
+const MIN_HYBRID_SLICE: u64 = 65536;
+
+// What the threads are sent, None tells them to exit
+type Chunk = Option<Job>;
+
+pub trait Generator {
+    // Something that makes candidates itself, in place of a wordlist /*{{{*/
+    // Add newline separated candidates to chunk until it's about size bytes,
+    // returning false once there are none left
+    fn fill(&mut self, chunk: &mut Vec<u8>, size: usize) -> bool;
+}
+/*}}}*/
+
+struct Combinator {
+    // The right hand side of a combinator attack, shared by all the threads /*{{{*/
+    right: Mmap, // every word in here gets stuck on the end of every left word
+    separator: Vec<u8>,
+    left_rule: Option<Rule>,
+    right_rule: Option<Rule>,
 }
 /*}}}*/
 
-fn setup_workers(hashes: &Hashes) -> Workers {
+fn setup_workers(
+    hashes: &Arc<Hashes>,
+    rules: Option<&Arc<Vec<Rule>>>,
+    masks: &[Arc<Mask>],
+    combinator: Option<&Arc<Combinator>>,
+    options: &Options,
+) -> Result<Workers, Box<dyn Error>> {
     // Fire off our worker threads to wait for the data from the wordlist /*{{{*/
     let threadnum = num_cpus::get(); // set the number of threads to the number of cores
     let mut threadhand: Vec<JoinHandle<_>> = Vec::new();
     // We clone the reciever multiple times which is how the threads pick up new clears
     // Can't do that with mpsc which only allows cloning the sender, need crossbeam
     let (tx, rx): (
-        crossbeam_channel::Sender<Option<Vec<u8>>>,
-        crossbeam_channel::Receiver<Option<Vec<u8>>>,
+        crossbeam_channel::Sender<Chunk>,
+        crossbeam_channel::Receiver<Chunk>,
     ) = unbounded();
     let (tx2, rx2): (
         crossbeam_channel::Sender<Stats>,
         crossbeam_channel::Receiver<Stats>,
     ) = unbounded();
+    let cracked_from: Arc<Vec<AtomicUsize>> =
+        Arc::new(options.wordlists.iter().map(|_| AtomicUsize::new(0)).collect());
+    let progress: Arc<Mutex<Progress>> = Arc::default();
+    // work the keyspaces out once rather than for every word
+    let masks: Vec<(Arc<Mask>, u64)> =
+        masks.iter().map(|mask| (Arc::clone(mask), mask.keyspace().unwrap())).collect();
+    // once there's more than a slice of mask for each word, share each word
+    // out between the threads rather than leaving a short wordlist to one
+    let expansion = masks.iter().fold(0_u64, |sum, (_, keyspace)| sum.saturating_add(*keyspace));
+    let split = match options.attack {
+        Attack::HybridWordMask | Attack::HybridMaskWord if expansion > MIN_HYBRID_SLICE => masks.clone(),
+        _ => Vec::new(),
+    };
 
     for _ in 0..threadnum {
         //for j in 0..threadnum {
         // Make copies of these two for the threads
         let rx_thread = rx.clone();
-        let tx2_thread = tx2.clone();
-        let hashes_thread = hashes.clone();
+        let mut cracker = Cracker::new(hashes, options, tx2.clone())?;
+        cracker.cracked_from = Arc::clone(&cracked_from);
         //let to_find_thread = hashes.hashlist.clone();
+        let encoding = options.encoding;
+        let rules = rules.cloned();
+        let masks = masks.clone();
+        let combinator = combinator.cloned();
+        let attack = options.attack;
+        let progress = Arc::clone(&progress);
         threadhand.push(thread::spawn(move || {
             // The in-thread worker code /*{{{*/
-            // Pre-allocate to reduce alloc overhead
-            let mut out: Vec<u8> = Vec::with_capacity(8192);
             let mut utf16: [u8; 2048] = [0_u8; 2048]; //nobody hashes more than 1024 character passwords right?! cc @crypt0rr
-            let mut b = [0; 2]; // needed for utf16 encoding, but not used
-            let mut stats = Stats {
-                cracked: 0,
-                hashed: 0,
-                waits: 0,
-                kbs: 0, // not used here
-            };
+            let mut unhexed: Vec<u8> = Vec::with_capacity(1024);
+            let mut mangled: Vec<u8> = Vec::with_capacity(1024); // candidates after rules or masks
+            // the combinator needs somewhere to mangle each side before joining them
+            let mut left: Vec<u8> = Vec::with_capacity(1024);
+            let mut right_unhexed: Vec<u8> = Vec::with_capacity(1024);
+            let mut right: Vec<u8> = Vec::with_capacity(1024);
+            let mut waits_sent = 0; // how many of our waits the main thread knows about
 
             // Fetch clears from the channel
             loop {
@@ -281,90 +1175,193 @@ fn setup_workers(hashes: &Hashes) -> Workers {
                 if let Ok(recv) = rx_thread.try_recv() {
                     // We wrap the message in an Option to allow for a kill signal
                     // Our thread recieved None lets dump our buffer and exit
-                    if recv == None {
+                    let Some(job) = recv else {
                         //println!("Break {}",j);
-                        stdout().write_all(&out).unwrap();
-                        tx2_thread.send(stats).unwrap();
+                        cracker.flush();
+                        cracker.tx2.send(Stats { waits: cracker.stats.waits - waits_sent, ..cracker.stats }).unwrap();
                         break;
+                    };
+                    // once we're interrupted, whatever's still queued is left for a restore
+                    if interrupted() {
+                        continue;
                     }
+                    // Keep track of the wordlist, so cracks are counted against it
+                    let origin = match &job {
+                        Job::Words(_, wordlist, offset) | Job::Hybrid(_, wordlist, offset, ..) => {
+                            Some((*wordlist, *offset))
+                        }
+                        _ => None,
+                    };
+                    cracker.wordlist = origin.map_or(NO_WORDLIST, |(wordlist, _)| wordlist);
                     // We got some clears to crack
-                    if let Some(message) = recv {
-                        for clear in message.split(|c| *c == 10_u8).filter(|l| !l.is_empty()) {
-                            stats.hashed += 1;
-                            //println!("Thread {} recieved: '{:?}'",j,std::str::from_utf8(clear));
-
-                            for (dst, src) in utf16.chunks_mut(2).zip(clear.iter()) {
-                                // faster to iter & encode chars than the encode_utf16 str iter
-                                let c = char::from(*src).encode_utf16(&mut b);
-                                // align_to is unsafe, but faster than to_le_bytes
-                                unsafe {
-                                    dst.copy_from_slice(c.align_to::<u8>().1);
-                                }
-                            }
-
-                            // encoding error
-                            if utf16.is_empty() {
-                                continue;
-                            }
-                            // doing this single Md4 digest is faster than
-                            // multiple updates() + finalize()
-                            let mut md = md4::MD4::new();
-                            md.digest(&utf16[..clear.len()*2]);
-                            //md.digest(&utf16);
-                            let hash = md.get_hash();
-
-                            if !hashes_thread.big {
-                                // for small hashlists, can we get away with this cheaper check
-                                if !hashes_thread.starts[hash[0] as usize]
-                                    || !hashes_thread.ends[hash[15] as usize]
-                                {
-                                    continue;
+                    match job {
+                        Job::Words(message, _, offset) if encoding == Encoding::Utf16Le => {
+                            // already encoded, so straight to the hashing
+                            for clear in encoding::utf16le_lines(&message) {
+                                if interrupted() {
+                                    break;
                                 }
+                                cracker.offset = offset
+                                    + (clear.as_ptr() as usize - message.as_ptr() as usize) as u64;
+                                cracker.check(clear, clear);
                             }
-
-                            // check if the generated hash is in our input hash list
-                            if hashes_thread.hashlist.contains_key(&hash) {
-                                stats.cracked += 1;
-                                //writing each character is faster than doing it in one go
-                                for x in hash {
-                                    write!(&mut out, "{:02x}", x).unwrap();
-                                }
-                                // extend_from_slice is faster than push
-                                out.extend_from_slice(&[58]); // colon
-                                out.extend_from_slice(clear); // clear text
-                                out.extend_from_slice(&[10]); // newline
-                                // check if our output buffer should be flushed
-                                if out.len() >= 8192 {
-                                    // make sure this comparison aligns with capacity
-                                    stdout().write_all(&out).unwrap();
-                                    out.clear();
+                        }
+                        Job::Words(message, _, offset) => {
+                            for clear in message.split(|c| *c == 10_u8).filter(|l| !l.is_empty())
+                            {
+                                if interrupted() {
+                                    break;
                                 }
-                                // update the main process on progress
-                                if stats.cracked == hashes_thread.updatethresh {
-                                    tx2_thread.send(stats).unwrap();
-                                    stats.cracked = 0;
-                                    stats.hashed = 0;
+                                cracker.offset = offset
+                                    + (clear.as_ptr() as usize - message.as_ptr() as usize) as u64;
+                                //println!("Thread {} recieved: '{:?}'",j,std::str::from_utf8(clear));
+                                // hashcat writes awkward candidates as $HEX[...]
+                                let clear = encoding::unhex(clear, &mut unhexed);
+                                match attack {
+                                    Attack::HybridWordMask | Attack::HybridMaskWord => {
+                                        // every mask candidate gets stuck on every word
+                                        let append = attack == Attack::HybridWordMask;
+                                        for (mask, keyspace) in &masks {
+                                            let slice = (&**mask, 0, *keyspace);
+                                            let flow = cracker.try_hybrid(
+                                                clear,
+                                                slice,
+                                                append,
+                                                &mut mangled,
+                                                &mut utf16,
+                                            );
+                                            if flow.is_break() {
+                                                break;
+                                            }
+                                        }
+                                    }
+                                    Attack::Combinator => {
+                                        // every word in the right list gets stuck on every word
+                                        let combinator = combinator.as_deref().unwrap();
+                                        let clear = match &combinator.left_rule {
+                                            Some(rule) if !rule.apply(clear, &mut left) => continue,
+                                            Some(_) => &left[..],
+                                            None => clear,
+                                        };
+                                        for word in combinator
+                                            .right
+                                            .split(|c| *c == 10_u8)
+                                            .filter(|l| !l.is_empty())
+                                        {
+                                            if interrupted() {
+                                                break;
+                                            }
+                                            let word = encoding::unhex(word, &mut right_unhexed);
+                                            let word = match &combinator.right_rule {
+                                                Some(rule) if !rule.apply(word, &mut right) => continue,
+                                                Some(_) => &right[..],
+                                                None => word,
+                                            };
+                                            mangled.clear();
+                                            mangled.extend_from_slice(clear);
+                                            mangled.extend_from_slice(&combinator.separator);
+                                            mangled.extend_from_slice(word);
+                                            cracker.try_clear(&mangled, &mut utf16);
+                                        }
+                                    }
+                                    _ => match &rules {
+                                        None => cracker.try_clear(clear, &mut utf16),
+                                        // every rule gets applied to every word
+                                        Some(rules) => {
+                                            for rule in rules.iter() {
+                                                if rule.apply(clear, &mut mangled) {
+                                                    cracker.try_clear(&mangled, &mut utf16);
+                                                }
+                                            }
+                                        }
+                                    },
                                 }
                             }
                         }
+                        Job::Hybrid(word, _, offset, mask, start, end) => {
+                            cracker.offset = offset;
+                            let word = encoding::unhex(&word, &mut unhexed);
+                            let append = attack == Attack::HybridWordMask;
+                            let slice = (&*mask, start, end);
+                            let _ = cracker.try_hybrid(word, slice, append, &mut mangled, &mut utf16);
+                        }
+                        Job::Mask(mask, start, end) => {
+                            let _ = mask.for_each(start, end, |clear| {
+                                if interrupted() {
+                                    return ControlFlow::Break(());
+                                }
+                                cracker.try_clear(clear, &mut utf16);
+                                ControlFlow::Continue(())
+                            });
+                        }
+                        Job::Markov(markov, len, start, end) => {
+                            markov.for_each(len, start, end, |clear| {
+                                cracker.try_clear(clear, &mut utf16)
+                            });
+                        }
+                    }
+                    // that chunk's done, unless we broke off part way through,
+                    // so once what it cracked is in the potfile it's safe to
+                    // restart after it
+                    if let Some(origin) = origin {
+                        cracker.flush();
+                        if !interrupted() {
+                            progress.lock().unwrap().finished(origin);
+                        }
                     }
+                    // keep the main thread's counts current, waits keep adding
+                    // up as they're what we back off with
+                    let waits = cracker.stats.waits - waits_sent;
+                    cracker.tx2.send(Stats { waits, ..cracker.stats }).unwrap();
+                    waits_sent = cracker.stats.waits;
+                    cracker.stats.cracked = 0;
+                    cracker.stats.hashed = 0;
                 }
                 while rx_thread.is_empty() {
-                    stats.waits += 1;
+                    cracker.stats.waits += 1;
                     //write!(&stdout(),"{}.",count).unwrap();
-                    thread::sleep(std::time::Duration::from_millis(stats.waits as u64));
+                    thread::sleep(std::time::Duration::from_millis(cracker.stats.waits as u64));
                 }
             }
+            cracker.loopback.unwrap_or_default()
         }));
         /*}}}*/
     }
-    Workers {
+    Ok(Workers {
         threadnum,
         threadhand,
+        max_queued: threadnum * 4,
         tx,
         //rx: rx,
         //tx2: tx2,
         rx2,
+        cracked_from,
+        progress,
+        saver: None,
+        status: Status::new(hashes, options.status_timer),
+        checked: Cell::new(Instant::now()),
+        done: Cell::new(Stats::default()),
+        split,
+    })
+}
+/*}}}*/
+
+fn wait_for_workers(workers: &Workers, hashes: &Hashes, stats: &mut Stats) -> bool {
+    // Collect stats and wait for the queue to go down, true if all is cracked or we're interrupted /*{{{*/
+    // Not letting the queue get too long is what lets us stop early
+    loop {
+        while let Ok(recv_stats) = workers.rx2.try_recv() {
+            stats.add(&recv_stats);
+        }
+        workers.save_session(stats);
+        workers.show_status(stats);
+        if hashes.found.load(Ordering::Relaxed) == hashes.hashlist.len() || interrupted() {
+            return true;
+        }
+        if workers.tx.len() < workers.max_queued {
+            return false;
+        }
+        thread::sleep(std::time::Duration::from_millis(1_u64));
     }
 }
 /*}}}*/
@@ -375,20 +1372,16 @@ fn read_wordlist(
     workers: &Workers,
     hashes: &Hashes,
     block_size: usize,
+    (index, start): (usize, usize), // which wordlist this is, and where to start in it
 ) -> Result<Stats, Box<dyn Error>> {
     // Read the wordlist, send chunks to the worker threads & handle cache'ing /*{{{*/
 
-    let mut stats = Stats {
-        cracked: 0, // how many have we cracked
-        hashed: 0,  // how many hashes have we generated
-        waits: 0,   // how many times was a thread waiting
-        kbs: 0,     // amount of data read for perf stats
-    };
+    let mut stats = Stats::default();
     let mut count = 1; // optimisation counter to reduce expensive thread checkins
     let check_thresh = 50; // how often to check with the threads
 
     // Send chunks of the wordlist to the threads to deal with, but split on newlines
-    let mut pos = 0; // our current pointer/index into the wordlist
+    let mut pos = start; // our current pointer/index into the wordlist
     while pos < wordlist.length - 1 {
         // advance the cursor but not past the end of the file
         let mut to = match pos {
@@ -396,26 +1389,29 @@ fn read_wordlist(
             _ => pos + chunk_size,
         };
         // find a newline to end on to save threads having to do it
-        while wordlist.mmap[to - 1] != 10 && to < wordlist.length {
-            to += 1;
+        if wordlist.utf16 {
+            // UTF-16LE newlines are two bytes, and have to be on a character boundary
+            while wordlist.mmap[to - 2..to] != [10, 0] && to + 2 <= wordlist.length {
+                to += 2;
+            }
+        } else {
+            while wordlist.mmap[to - 1] != 10 && to < wordlist.length {
+                to += 1;
+            }
         }
         // send it to the threads
-        workers.tx.send(Some(wordlist.mmap[pos..to].to_vec()))?;
+        workers.send_words(wordlist.mmap[pos..to].to_vec(), index, pos as u64)?;
         // update the bytes counter
         stats.kbs += (to - pos) / 1024;
-        // update the cursor position
-        pos = to - 1;
-        // only checkin with threads sometimes to prevent slowdowns
-        if count % check_thresh == 0 {
+        // update the cursor position, UTF-16 chunks can't overlap or they'd
+        // stop being aligned
+        pos = if wordlist.utf16 { to } else { to - 1 };
+        // only checkin with threads sometimes to prevent slowdowns, unless
+        // we're getting too far ahead of them
+        if count % check_thresh == 0 || workers.tx.len() >= workers.max_queued || interrupted() {
             // check if we can exit early because we cracked everything
-            if let Ok(recv_stats) = workers.rx2.try_recv() {
-                stats.cracked += recv_stats.cracked;
-                stats.hashed += recv_stats.hashed;
-                stats.waits += recv_stats.waits;
-                // if we can exit early stop reading the wordlist and try exit
-                if stats.cracked == hashes.hashlist.len() {
-                    break;
-                }
+            if wait_for_workers(workers, hashes, &mut stats) {
+                break;
             }
         }
         count += 1;
@@ -439,7 +1435,7 @@ fn read_wordlist(
                 _ if (wordlist.cache_point + wordlist.cache_size / 2) >= wordlist.length => {
                     wordlist.length
                 }
-                _ => (wordlist.cache_point + wordlist.cache_size / 2),
+                _ => wordlist.cache_point + wordlist.cache_size / 2,
             };
             /*
             // Some debugging stats
@@ -447,7 +1443,7 @@ fn read_wordlist(
             let mut answer = vec![0u8; wordlist.pages];
             mincore_check(&wordlist.mmap, wordlist.length, &mut answer);
             percent_cached = gen_stats(&answer, wordlist.pages);
-            println!("[+] Purging up first {:.2}% bytes from cache
+            info!("[+] Purging up first {:.2}% bytes from cache
           Cache point now at {:.2}%, Total in cache now {percent_cached:.2}%",(pos as f64/wordlist.length as f64) * 100_f64,(wordlist.cache_point as f64/wordlist.length as f64) *100_f64);
             */
         }
@@ -456,16 +1452,1069 @@ fn read_wordlist(
 }
 /*}}}*/
 
+fn send_slices(
+    keyspace: u64,
+    workers: &Workers,
+    hashes: &Hashes,
+    stats: &mut Stats,
+    job: impl Fn(u64, u64) -> Job,
+) -> Result<bool, Box<dyn Error>> {
+    // Split a keyspace into slices for the threads, true if all is cracked /*{{{*/
+    // big enough to keep a thread busy for a while, but small keyspaces still
+    // get spread over all the threads
+    let max_slice = 4_194_304;
+    let slice = (keyspace / workers.threadnum as u64).clamp(1, max_slice);
+    let mut start = 0;
+    while start < keyspace {
+        let end = keyspace.min(start + slice);
+        workers.tx.send(Some(job(start, end)))?;
+        start = end;
+        if workers.throttle(hashes, stats) {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+/*}}}*/
+
+fn split_point(buf: &[u8], utf16: bool) -> Option<usize> {
+    // Where the last whole line in buf ends /*{{{*/
+    match utf16 {
+        // UTF-16LE newlines are two bytes, and have to be on a character boundary
+        true => buf.chunks_exact(2).rposition(|unit| unit == [10, 0]).map(|i| i * 2 + 2),
+        false => buf.iter().rposition(|c| *c == 10).map(|i| i + 1),
+    }
+}
+/*}}}*/
+
+fn next_chunk(
+    input: &mut dyn Read,
+    pending: &mut Vec<u8>,
+    chunk_size: usize,
+    utf16: bool,
+) -> std::io::Result<Option<Vec<u8>>> {
+    // Read the next newline aligned chunk from a stream, None once it's done /*{{{*/
+    loop {
+        // read_to_end carries on until the block is full or the input ends
+        let want = chunk_size.saturating_sub(pending.len()).max(4096);
+        let read = input.take(want as u64).read_to_end(pending)?;
+        if read < want {
+            return Ok((!pending.is_empty()).then(|| std::mem::take(pending)));
+        }
+        // a line longer than a chunk, keep reading until it ends
+        if let Some(split) = split_point(pending, utf16) {
+            let rest = pending.split_off(split);
+            return Ok(Some(std::mem::replace(pending, rest)));
+        }
+    }
+}
+/*}}}*/
+
+fn read_threaded(
+    mut input: Box<dyn Read + Send>,
+    chunk_size: usize,
+    workers: &Workers,
+    hashes: &Hashes,
+    utf16: bool,
+    (index, skip): (usize, u64), // which wordlist this is, and how much of it to skip
+    mut sent: impl FnMut(u64),   // told how far through we are after each chunk
+) -> Result<Stats, Box<dyn Error>> {
+    // Read a stream in its own thread, sending its chunks on to the threads /*{{{*/
+    // A slow reader can block for as long as it likes without holding up
+    // stopping, the status line or the session
+    let mut stats = Stats::default();
+    // just enough queued up that the reader never waits on us
+    let (tx, rx) = bounded::<Vec<u8>>(2);
+    let reader = thread::spawn(move || -> Result<(), String> {
+        // there's no seeking in a stream, so read up to where we restart
+        std::io::copy(&mut (&mut input).take(skip), &mut std::io::sink()).map_err(|e| e.to_string())?;
+        let mut pending: Vec<u8> = Vec::with_capacity(chunk_size);
+        while let Some(chunk) = next_chunk(&mut input, &mut pending, chunk_size, utf16)
+            .map_err(|e| e.to_string())?
+        {
+            // the receiver's gone if we stopped early
+            if tx.send(chunk).is_err() {
+                break;
+            }
+        }
+        Ok(())
+    });
+    let mut offset = skip;
+    loop {
+        let chunk = match rx.recv_timeout(Duration::from_millis(100)) {
+            Ok(chunk) => chunk,
+            Err(RecvTimeoutError::Timeout) if workers.throttle(hashes, &mut stats) => break,
+            Err(RecvTimeoutError::Timeout) => continue,
+            // the reader's done, so it won't keep us waiting
+            Err(RecvTimeoutError::Disconnected) => {
+                reader.join().unwrap()?;
+                return Ok(stats);
+            }
+        };
+        stats.kbs += chunk.len() / 1024;
+        let len = chunk.len() as u64;
+        workers.send_words(chunk, index, offset)?;
+        offset += len;
+        sent(offset);
+        if workers.throttle(hashes, &mut stats) {
+            break;
+        }
+    }
+    // we stopped early, and the reader could be stuck waiting on a pipe, so
+    // leave it to notice the receiver's gone whenever it next gets something
+    Ok(stats)
+}
+/*}}}*/
+
+fn read_stream(
+    path: &str,
+    chunk_size: usize,
+    workers: &Workers,
+    hashes: &Hashes,
+    utf16: bool,
+    from: (usize, u64), // which wordlist this is, and how much of it to skip
+) -> Result<Stats, Box<dyn Error>> {
+    // Read stdin or a pipe in blocks and send them to the threads on newlines /*{{{*/
+    // There's no cache to manage, whatever is writing to us decides how fast we go
+    // a restored stream has to be piped in again, and we throw away what was done
+    let input = open_stream(path)?;
+    read_threaded(input, chunk_size, workers, hashes, utf16, from, |_| {})
+        .map_err(|e| format!("Failed to read {path}: {e}").into())
+}
+/*}}}*/
+
+fn read_compressed(
+    path: &str,
+    compression: Compression,
+    chunk_size: usize,
+    workers: &Workers,
+    hashes: &Hashes,
+    utf16: bool,
+    from: (usize, u64), // which wordlist this is, and how much of it to skip
+) -> Result<Stats, Box<dyn Error>> {
+    // Decompress a wordlist in its own thread and send its chunks to the threads /*{{{*/
+    // Decompressing is slow enough that doing it here would starve the
+    // threads, so we only pass the chunks on
+    let compressed_bytes = Arc::new(AtomicUsize::new(0));
+    let input = compressed::open(path, compression, Arc::clone(&compressed_bytes))?;
+    let mut stats = read_threaded(input, chunk_size, workers, hashes, utf16, from, |offset| {
+        let read = compressed_bytes.load(Ordering::Relaxed) as u64;
+        workers.status.compressed.set(Some((read, offset)));
+    })
+    .map_err(|e| format!("Failed to decompress {path}: {e}"))?;
+    stats.compressed_kbs = compressed_bytes.load(Ordering::Relaxed) / 1024;
+    Ok(stats)
+}
+/*}}}*/
+
+fn wordlist_files(dir: &Path, files: &mut Vec<(u64, PathBuf)>) -> Result<(), Box<dyn Error>> {
+    // Find every wordlist under a directory, along with its size /*{{{*/
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        // skip .git, .DS_Store and the like
+        if path.file_name().is_some_and(|name| name.to_string_lossy().starts_with('.')) {
+            continue;
+        }
+        let metadata = fs::metadata(&path)?;
+        if metadata.is_dir() {
+            wordlist_files(&path, files)?;
+        } else if metadata.len() > 0 {
+            files.push((metadata.len(), path));
+        }
+    }
+    Ok(())
+}
+/*}}}*/
+
+fn expand_wordlists(paths: &[String], manifest: Option<&str>) -> Result<Vec<String>, Box<dyn Error>> {
+    // Turn the wordlists, directories and manifest into the files to read, in order /*{{{*/
+    let mut paths = paths.to_vec();
+    // A manifest lists wordlists one per line, relative to where it is
+    if let Some(manifest) = manifest {
+        let contents = fs::read_to_string(manifest)?;
+        let base = Path::new(manifest).parent().unwrap_or(Path::new(""));
+        for line in contents.lines().map(str::trim) {
+            if !line.is_empty() && !line.starts_with('#') {
+                paths.push(base.join(line).to_string_lossy().into_owned());
+            }
+        }
+    }
+    let mut wordlists = Vec::new();
+    for path in paths {
+        if path == "-" || !Path::new(&path).is_dir() {
+            wordlists.push(path);
+            continue;
+        }
+        // smallest first, they're quick to get through and the small lists
+        // tend to be the good ones
+        let mut files = Vec::new();
+        wordlist_files(Path::new(&path), &mut files)?;
+        if files.is_empty() {
+            info!("[!] There are no wordlists in {path}");
+        }
+        files.sort();
+        wordlists.extend(files.into_iter().map(|(_, file)| file.to_string_lossy().into_owned()));
+    }
+    Ok(wordlists)
+}
+/*}}}*/
+
+fn read_wordlists(
+    options: &Options,
+    (chunk_size, cache_size, block_size): (usize, usize, usize),
+    workers: &Workers,
+    hashes: &Hashes,
+    resume: (usize, u64), // the wordlist and offset a restored session got to
+) -> Result<Vec<Stats>, Box<dyn Error>> {
+    // Read each wordlist in turn to the same threads, returning their stats /*{{{*/
+    // Stops before the next wordlist once everything has been cracked, so
+    // the list is shorter than the wordlists if that happens
+    let utf16 = options.encoding == Encoding::Utf16Le;
+    let total = options.wordlists.len();
+    let mut read = Vec::new();
+    for (index, path) in options.wordlists.iter().enumerate() {
+        // the restored session already did these
+        if index < resume.0 {
+            read.push(Stats::default());
+            continue;
+        }
+        let start = if index == resume.0 { resume.1 } else { 0 };
+        if hashes.found.load(Ordering::Relaxed) == hashes.hashlist.len() {
+            info!("[*] Everything is cracked, skipping the last {} wordlists", total - index);
+            break;
+        }
+        if interrupted() {
+            break;
+        }
+        if total > 1 {
+            info!("[+] Wordlist {}/{total}: {path}", index + 1);
+        }
+        // there's nothing to read, and an empty file can't be mapped
+        if !is_stream(path) && fs::metadata(path)?.len() == 0 {
+            info!("[!] Skipping {path}, it's empty");
+            read.push(Stats::default());
+            continue;
+        }
+        if start > 0 {
+            info!("[+] Restarting {path} from byte {start}");
+        }
+        workers.progress.lock().unwrap().sent = (index, start);
+        workers.status.compressed.set(None);
+        let started = Instant::now();
+        let stats = if is_stream(path) {
+            read_stream(path, chunk_size, workers, hashes, utf16, (index, start))?
+        } else if let Some(compression) = compressed::detect(path)? {
+            // compressed wordlists are decompressed as they're read rather than cached
+            info!("[+] Decompressing the {} wordlist as it's read", compression.name());
+            let from = (index, start);
+            read_compressed(path, compression, chunk_size, workers, hashes, utf16, from)?
+        } else {
+            let start = start as usize;
+            let mut wordlist = initialise_wordlist(path, cache_size, block_size, utf16, start)?;
+            read_wordlist(&mut wordlist, chunk_size, workers, hashes, block_size, (index, start))?
+        };
+        workers.done.set(workers.totals(&stats));
+        // the threads are still on the last of it, so its cracks are in the table at the end
+        if total > 1 {
+            info!("[+] Read {} kB of {path} in {:.2} s", stats.kbs, started.elapsed().as_secs_f64());
+        }
+        read.push(stats);
+    }
+    Ok(read)
+}
+/*}}}*/
+
+fn read_masks(
+    masks: &[Arc<Mask>],
+    workers: &Workers,
+    hashes: &Hashes,
+) -> Result<Stats, Box<dyn Error>> {
+    // Split each mask's keyspace into slices and send them to the threads /*{{{*/
+    let mut stats = Stats::default();
+    for mask in masks {
+        // masks are checked to fit when they're parsed
+        let keyspace = mask.keyspace().unwrap();
+        info!("[+] Mask of length {} has {keyspace} candidates", mask.len());
+        let job = |start, end| Job::Mask(Arc::clone(mask), start, end);
+        if send_slices(keyspace, workers, hashes, &mut stats, job)? {
+            break;
+        }
+    }
+    Ok(stats)
+}
+/*}}}*/
+
+fn read_markov(
+    markov: &Arc<Markov>,
+    lengths: RangeInclusive<usize>,
+    workers: &Workers,
+    hashes: &Hashes,
+) -> Result<Stats, Box<dyn Error>> {
+    // Send slices of each length's Markov keyspace to the threads, shortest first /*{{{*/
+    let mut stats = Stats::default();
+    for len in lengths {
+        let Some(keyspace) = markov.keyspace(len) else {
+            info!("[!] Markov length {len} has too many candidates, stopping");
+            break;
+        };
+        info!("[+] Markov length {len} has {keyspace} candidates");
+        let job = |start, end| Job::Markov(Arc::clone(markov), len, start, end);
+        if send_slices(keyspace, workers, hashes, &mut stats, job)? {
+            break;
+        }
+    }
+    Ok(stats)
+}
+/*}}}*/
+
+fn crack_usernames(
+    hashes: &Arc<Hashes>,
+    options: &Options,
+    stats: &mut Stats,
+) -> Result<Vec<Vec<u8>>, Box<dyn Error>> {
+    // Try guesses made from each account's name against just its own hash /*{{{*/
+    let targets: Vec<(&[u8; 16], &Target)> = hashes
+        .hashlist
+        .iter()
+        .filter(|(_, target)| !target.accounts.is_empty())
+        .collect();
+    if targets.is_empty() {
+        return Ok(Vec::new());
+    }
+    info!("[+] Trying guesses based on the names of {} hashes' accounts", targets.len());
+    // Account names are text, whatever encoding the wordlist is in
+    let options = Options {
+        encoding: match options.encoding {
+            Encoding::Utf16Le => Encoding::Utf8,
+            encoding => encoding,
+        },
+        ..options.clone()
+    };
+    let (tx2, rx2) = unbounded();
+    let threadnum = num_cpus::get();
+    let mut crackers = (0..threadnum)
+        .map(|_| Cracker::new(hashes, &options, tx2.clone()))
+        .collect::<Result<Vec<_>, _>>()?;
+    let years = options.years.clone();
+
+    thread::scope(|scope| {
+        let slices = targets.chunks(targets.len().div_ceil(threadnum));
+        for (cracker, targets) in crackers.iter_mut().zip(slices) {
+            let years = years.clone();
+            scope.spawn(move || {
+                let mut utf16: [u8; 2048] = [0_u8; 2048];
+                for (hash, target) in targets {
+                    // history entries share a name, only guess it once per hash
+                    let mut names = HashSet::new();
+                    for account in &target.accounts {
+                        if !names.insert((&account.username, &account.domain)) {
+                            continue;
+                        }
+                        let domain = account.domain.as_deref();
+                        for clear in usernames::candidates(&account.username, domain, years.clone()) {
+                            if target.cracked.load(Ordering::Relaxed) {
+                                break;
+                            }
+                            cracker.try_target(&clear, &mut utf16, hash, target);
+                        }
+                    }
+                }
+                cracker.flush();
+            });
+        }
+    });
+
+    // the crackers send their stats early if they crack a lot
+    while let Ok(recv_stats) = rx2.try_recv() {
+        stats.add(&recv_stats);
+    }
+    let mut cracked = Vec::new();
+    for cracker in crackers {
+        stats.add(&cracker.stats);
+        cracked.extend(cracker.loopback.unwrap_or_default());
+    }
+    info!("[+] Cracked {} hashes with guesses based on account names", stats.cracked);
+    Ok(cracked)
+}
+/*}}}*/
+
+fn read_generator(
+    generator: &mut dyn Generator,
+    chunk_size: usize,
+    workers: &Workers,
+    hashes: &Hashes,
+) -> Result<Stats, Box<dyn Error>> {
+    // Send what a generator makes to the threads, like chunks of a wordlist /*{{{*/
+    let mut stats = Stats::default();
+    loop {
+        let mut chunk: Vec<u8> = Vec::with_capacity(chunk_size + 1024);
+        let more = generator.fill(&mut chunk, chunk_size);
+        if !chunk.is_empty() {
+            stats.kbs += chunk.len() / 1024;
+            workers.tx.send(Some(Job::Words(chunk, NO_WORDLIST, 0)))?;
+        }
+        if !more {
+            break;
+        }
+        if workers.throttle(hashes, &mut stats) {
+            break;
+        }
+    }
+    Ok(stats)
+}
+/*}}}*/
+
+fn read_clears(
+    clears: &[Vec<u8>],
+    chunk_size: usize,
+    workers: &Workers,
+    hashes: &Hashes,
+) -> Result<Stats, Box<dyn Error>> {
+    // Send cracked clears back to the threads for a loopback pass /*{{{*/
+    let mut stats = Stats::default();
+    let mut chunk: Vec<u8> = Vec::with_capacity(chunk_size);
+    for (i, clear) in clears.iter().enumerate() {
+        // the threads unhex them again, same as they would from a wordlist
+        encoding::write_hex(&mut chunk, clear);
+        chunk.push(10);
+        if chunk.len() < chunk_size && i + 1 < clears.len() {
+            continue;
+        }
+        stats.kbs += chunk.len() / 1024;
+        workers.tx.send(Some(Job::Words(std::mem::take(&mut chunk), NO_WORDLIST, 0)))?;
+        if workers.throttle(hashes, &mut stats) {
+            break;
+        }
+    }
+    Ok(stats)
+}
+/*}}}*/
+
+fn finish_workers(mut workers: Workers, stats: &mut Stats) -> Result<Vec<Vec<u8>>, Box<dyn Error>> {
+    // Let the threads finish what's queued, then collect their stats & loopback clears /*{{{*/
+    // Make sure the workers have picked up all the chunks
+    loop {
+        if workers.tx.is_empty() {
+            break;
+        }
+        // this can take a while with big chunks queued, so keep saving
+        workers.save_session(stats);
+        workers.show_status(stats);
+        thread::sleep(std::time::Duration::from_millis(2_u64));
+    }
+    // tell the threads to exit, as many times as there are threads
+    for _ in 0..workers.threadnum {
+        workers.tx.send(None)?;
+    }
+    // wait for threads to exit
+    let mut cracked = Vec::new();
+    for thread in std::mem::take(&mut workers.threadhand) {
+        cracked.extend(thread.join().unwrap());
+    }
+
+    // get final numbers
+    while let Ok(recv_stats) = workers.rx2.try_recv() {
+        stats.add(&recv_stats);
+    }
+    // save where we got to, now the threads have finished what they could
+    if let Some(saver) = workers.saver.as_ref().filter(|_| interrupted()) {
+        saver.save(&workers.progress, &workers.totals(stats))?;
+        info!("[*] Saved the session to {}, carry on with --restore", saver.path);
+    }
+    Ok(cracked)
+}
+/*}}}*/
+
+#[derive(Clone, Copy, PartialEq)]
+enum Attack {
+    // Where the candidates come from /*{{{*/
+    Straight,       // a wordlist, optionally with rules
+    Combinator,     // every word in one wordlist joined to every word in another
+    Mask,           // brute force with a hashcat style mask
+    HybridWordMask, // every mask candidate appended to each word
+    HybridMaskWord, // every mask candidate prepended to each word
+    Patterns,       // seasons, months & company names with years and symbols
+    Keywalk,        // walks along the keyboard like 1qaz2wsx
+    Prince,         // words from a wordlist chained together
+    Markov,         // brute force, likeliest characters first
+}
+/*}}}*/
+
+#[derive(Clone)]
+struct Options {
+    // Structure to hold the command line options /*{{{*/
+    args: Vec<String>, // as given, for the session
+    attack: Attack,
+    hashes_path: String,
+    wordlists: Vec<String>, // straight attacks can have several, none for --show and --left
+    manifest_path: Option<String>, // more wordlists, in the order to use them
+    right_path: Option<String>,    // the second wordlist for the combinator
+    separator: String,
+    left_rule: Option<String>,
+    right_rule: Option<String>,
+    mask: Option<String>,
+    custom_charsets: [Option<String>; 4],
+    increment: bool,
+    increment_min: Option<usize>,
+    increment_max: Option<usize>,
+    encoding: Encoding,
+    potfile_path: Option<String>, // None if the potfile is disabled
+    outfile_path: Option<String>, // None for stdout
+    quiet: bool,
+    json: bool, // results as JSON lines rather than hash:clear
+    session_path: Option<String>, // None if sessions are disabled
+    status_timer: Option<Duration>, // None for only showing the status on SIGUSR1
+    rules_path: Option<String>,
+    loopback: bool,
+    loopback_rules_path: Option<String>, // falls back to rules_path
+    usernames: bool,                     // try guesses based on account names first
+    years: RangeInclusive<u32>,          // for the usernames and patterns
+    company_path: Option<String>,
+    layouts: Vec<String>, // keyboard layouts to walk
+    walk_min: usize,
+    walk_max: usize,
+    walk_turns: usize,
+    walk_shifts: usize,
+    prince: prince::Settings,
+    markov_train: Option<String>, // write a Markov model from the inputs and exit
+    train_inputs: Vec<String>,
+    markov_hcstat: Option<String>,
+    markov_threshold: usize,
+    markov_min: usize,
+    markov_max: usize,
+    show: bool,
+    left: bool,
+}
+/*}}}*/
+
+const USAGE: &str = "Usage: ntcrack [options] <input hashlist> <wordlist or directory>...
+       ntcrack [options] -a 1 <input hashlist> <left wordlist> <right wordlist>
+       ntcrack [options] -a 3 <input hashlist> <mask>
+       ntcrack [options] -a 6 <input hashlist> <wordlist> <mask>
+       ntcrack [options] -a 7 <input hashlist> <mask> <wordlist>
+       ntcrack [options] -a patterns <input hashlist>
+       ntcrack [options] -a keywalk <input hashlist>
+       ntcrack [options] -a prince <input hashlist> <wordlist>
+       ntcrack [options] -a markov --markov-hcstat <model> <input hashlist>
+       ntcrack --markov-train <model> <potfile or wordlist>...
+       ntcrack [options] --show|--left <input hashlist>
+       ntcrack [--session <path>] --restore
+
+A <wordlist> of - is read from stdin, and FIFOs can be used too.
+gzip, zstd, xz and bzip2 compressed wordlists are decompressed as they're read.
+Straight attacks read each wordlist in turn, and directories smallest file first.
+
+Options:
+  -a, --attack-mode <mode> 0/straight (default), 1/combinator, 3/mask,
+                           6/hybrid-wordlist-mask, 7/hybrid-mask-wordlist, patterns,
+                           keywalk, prince or markov
+  -j, --rule-left <rule>   Single rule applied to each left word in combinator mode
+  -k, --rule-right <rule>  Single rule applied to each right word in combinator mode
+      --separator <str>    Put this between the left and right words in combinator mode
+  -1, -2, -3, -4 <charset> Custom charsets for ?1 to ?4 in masks, e.g. -1 ?l?d
+  -i, --increment          Try each length of the mask, from shortest to longest
+      --increment-min <n>  Shortest length to start --increment at
+      --increment-max <n>  Longest length to stop --increment at
+  -e, --encoding <enc>     Wordlist encoding: utf-8, latin-1, cp1252, utf-16le or auto
+                           (default auto)
+      --manifest <path>    Wordlists to read, one per line, after any given on the
+                           command line
+  -r, --rules <path>       Apply every rule in a hashcat/john rule file to every word
+      --loopback           Run what gets cracked back through the rules until nothing
+                           new cracks
+      --loopback-rules <path>
+                           Rules for --loopback if they're not the -r ones
+      --years <from-to>    Years to use in guesses (default the last ten and next)
+      --company <path>     Company names etc. to add to the patterns, one per line
+      --layout <names>     Keyboard layouts to walk, comma separated from us, uk, de
+                           and fr (default us)
+      --walk-min <n>       Shortest keyboard walk (default 4)
+      --walk-max <n>       Longest keyboard walk (default 10)
+      --walk-turns <n>     How many times a walk can change direction (default 1)
+      --walk-shifts <n>    How many times a walk can toggle shift (default 1)
+      --pw-min <n>         Shortest PRINCE candidate (default 1)
+      --pw-max <n>         Longest PRINCE candidate (default 16)
+      --elem-cnt-min <n>   Fewest words in a PRINCE chain (default 1)
+      --elem-cnt-max <n>   Most words in a PRINCE chain (default 8)
+      --skip <n>           Skip the first n PRINCE candidates
+      --limit <n>          Stop after n PRINCE candidates
+      --markov-train <path>
+                           Train a Markov model on potfiles and wordlists
+      --markov-hcstat <path>
+                           The Markov model to brute force with
+  -t, --markov-threshold <n>
+                           Try the n likeliest characters at each position (default 16)
+      --markov-min <n>     Shortest Markov candidate (default 1)
+      --markov-max <n>     Longest Markov candidate (default 8)
+      --no-usernames       Don't try guesses based on the hashlist's account names
+  -o, --outfile <path>     Append the cracked hashes to a file instead of printing them
+  -q, --quiet              Only print the cracked hashes, nothing else
+      --json               Print the cracked hashes and the stats as JSON lines
+      --potfile <path>     Where to keep cracked hashes (default ntcrack.potfile)
+      --potfile-disable    Don't read or write a potfile
+      --session <path>     Where to save the progress through the wordlists, to carry
+                           on with --restore (default ntcrack.session)
+      --session-disable    Don't save the session
+      --restore            Carry on the saved session, with the same options
+      --status-timer <n>   Show the status on stderr every n seconds, 0 for only when
+                           sent SIGUSR1 (default 10)
+      --show               Print the hashes in the hashlist that are in the potfile
+      --left               Print the hashes in the hashlist that aren't in the potfile
+  -h, --help               Print this help";
+
+fn parse_args(given: Vec<String>) -> Result<Options, Box<dyn Error>> {
+    // Pull our options and the hashlist & wordlist paths off the command line /*{{{*/
+    let mut args = given.clone().into_iter();
+    let mut positional = Vec::new();
+    let mut encoding = Encoding::Auto;
+    let mut potfile_path = Some(String::from("ntcrack.potfile"));
+    let mut potfile_disable = false;
+    let mut outfile_path = None;
+    let mut quiet = false;
+    let mut json = false;
+    let mut session_path = Some(String::from("ntcrack.session"));
+    let mut session_disable = false;
+    let mut status_timer = Some(Duration::from_secs(10));
+    let mut rules_path = None;
+    let mut loopback = false;
+    let mut loopback_rules_path = None;
+    let mut usernames = true;
+    let mut years = patterns::recent_years();
+    let mut company_path = None;
+    let mut manifest_path = None;
+    let mut layouts = vec![String::from("us")];
+    let mut walk_min = 4;
+    let mut walk_max = 10;
+    let mut walk_turns = 1;
+    let mut walk_shifts = 1;
+    let mut prince = prince::Settings {
+        pw_min: 1,
+        pw_max: 16,
+        elem_cnt_min: 1,
+        elem_cnt_max: 8,
+        skip: 0,
+        limit: None,
+    };
+    let mut markov_train = None;
+    let mut markov_hcstat = None;
+    let mut markov_threshold = 16;
+    let mut markov_min = 1;
+    let mut markov_max = 8;
+    let mut separator = String::new();
+    let mut left_rule = None;
+    let mut right_rule = None;
+    let mut attack = Attack::Straight;
+    let mut custom_charsets: [Option<String>; 4] = Default::default();
+    let mut increment = false;
+    let mut increment_min = None;
+    let mut increment_max = None;
+    let mut show = false;
+    let mut left = false;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-e" | "--encoding" => {
+                let name = args.next().ok_or("--encoding needs a value")?;
+                encoding = Encoding::from_name(&name).ok_or_else(|| {
+                    format!("Unknown encoding {name}, use utf-8, latin-1, cp1252, utf-16le or auto")
+                })?;
+            }
+            "-a" | "--attack-mode" => {
+                let mode = args.next().ok_or("--attack-mode needs a value")?;
+                attack = match mode.as_str() {
+                    "0" | "straight" => Attack::Straight,
+                    "1" | "combinator" => Attack::Combinator,
+                    "3" | "mask" => Attack::Mask,
+                    "6" | "hybrid-wordlist-mask" => Attack::HybridWordMask,
+                    "7" | "hybrid-mask-wordlist" => Attack::HybridMaskWord,
+                    "patterns" => Attack::Patterns,
+                    "keywalk" => Attack::Keywalk,
+                    "prince" => Attack::Prince,
+                    "markov" => Attack::Markov,
+                    _ => return Err(format!("Unknown attack mode {mode}").into()),
+                };
+            }
+            "-1" | "-2" | "-3" | "-4" => {
+                let charset = args.next().ok_or(format!("{arg} needs a charset"))?;
+                let n = arg[1..].parse::<usize>()?;
+                custom_charsets[n - 1] = Some(charset);
+            }
+            "-i" | "--increment" => increment = true,
+            "--increment-min" => {
+                let n = args.next().ok_or("--increment-min needs a length")?;
+                increment_min = Some(n.parse::<usize>()?);
+            }
+            "--increment-max" => {
+                let n = args.next().ok_or("--increment-max needs a length")?;
+                increment_max = Some(n.parse::<usize>()?);
+            }
+            "-j" | "--rule-left" => left_rule = Some(args.next().ok_or("--rule-left needs a rule")?),
+            "-k" | "--rule-right" => {
+                right_rule = Some(args.next().ok_or("--rule-right needs a rule")?);
+            }
+            "--separator" => separator = args.next().ok_or("--separator needs a value")?,
+            "-r" | "--rules" => rules_path = Some(args.next().ok_or("--rules needs a path")?),
+            "--loopback" => loopback = true,
+            "--loopback-rules" => {
+                loopback = true;
+                loopback_rules_path = Some(args.next().ok_or("--loopback-rules needs a path")?);
+            }
+            "--years" => {
+                let range = args.next().ok_or("--years needs a range")?;
+                years = patterns::parse_years(&range)?;
+            }
+            "--company" => company_path = Some(args.next().ok_or("--company needs a path")?),
+            "--manifest" => manifest_path = Some(args.next().ok_or("--manifest needs a path")?),
+            "--layout" => {
+                let names = args.next().ok_or("--layout needs a layout")?;
+                layouts = names.split(',').map(String::from).collect();
+                if let Some(name) = layouts.iter().find(|l| keywalk::Layout::from_name(l).is_none()) {
+                    return Err(format!("Unknown keyboard layout {name}, use us, uk, de or fr").into());
+                }
+            }
+            "--walk-min" => walk_min = args.next().ok_or("--walk-min needs a length")?.parse()?,
+            "--walk-max" => walk_max = args.next().ok_or("--walk-max needs a length")?.parse()?,
+            "--walk-turns" => walk_turns = args.next().ok_or("--walk-turns needs a number")?.parse()?,
+            "--walk-shifts" => {
+                walk_shifts = args.next().ok_or("--walk-shifts needs a number")?.parse()?;
+            }
+            "--pw-min" => prince.pw_min = args.next().ok_or("--pw-min needs a length")?.parse()?,
+            "--pw-max" => prince.pw_max = args.next().ok_or("--pw-max needs a length")?.parse()?,
+            "--elem-cnt-min" => {
+                prince.elem_cnt_min = args.next().ok_or("--elem-cnt-min needs a number")?.parse()?;
+            }
+            "--elem-cnt-max" => {
+                prince.elem_cnt_max = args.next().ok_or("--elem-cnt-max needs a number")?.parse()?;
+            }
+            "--skip" => prince.skip = args.next().ok_or("--skip needs a number")?.parse()?,
+            "--limit" => prince.limit = Some(args.next().ok_or("--limit needs a number")?.parse()?),
+            "--markov-train" => {
+                markov_train = Some(args.next().ok_or("--markov-train needs a path")?);
+            }
+            "--markov-hcstat" => {
+                markov_hcstat = Some(args.next().ok_or("--markov-hcstat needs a path")?);
+            }
+            "-t" | "--markov-threshold" => {
+                markov_threshold = args.next().ok_or("--markov-threshold needs a number")?.parse()?;
+            }
+            "--markov-min" => markov_min = args.next().ok_or("--markov-min needs a length")?.parse()?,
+            "--markov-max" => markov_max = args.next().ok_or("--markov-max needs a length")?.parse()?,
+            "--no-usernames" => usernames = false,
+            "--potfile" => potfile_path = Some(args.next().ok_or("--potfile needs a path")?),
+            "--potfile-disable" => potfile_disable = true,
+            "-o" | "--outfile" => outfile_path = Some(args.next().ok_or("--outfile needs a path")?),
+            "-q" | "--quiet" => quiet = true,
+            "--json" => json = true,
+            "--session" => session_path = Some(args.next().ok_or("--session needs a path")?),
+            "--session-disable" => session_disable = true,
+            "--status-timer" => {
+                let secs: u64 = args.next().ok_or("--status-timer needs a number of seconds")?.parse()?;
+                status_timer = (secs > 0).then(|| Duration::from_secs(secs));
+            }
+            "--restore" => return Err("--restore can only be given --session".into()),
+            "--show" => show = true,
+            "--left" => left = true,
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                std::process::exit(0);
+            }
+            _ if arg.starts_with('-') && arg != "-" => {
+                return Err(format!("Unknown option {arg}\n{USAGE}").into());
+            }
+            _ => positional.push(arg),
+        }
+    }
+    if potfile_disable {
+        potfile_path = None;
+    }
+    if session_disable {
+        session_path = None;
+    }
+    if rules_path.is_some() && encoding == Encoding::Utf16Le {
+        return Err("Rules can't be applied to UTF-16LE wordlists".into());
+    }
+    if loopback && rules_path.is_none() && loopback_rules_path.is_none() {
+        return Err("--loopback needs rules, from -r or --loopback-rules".into());
+    }
+    if show && left {
+        return Err("Use one of --show or --left, not both".into());
+    }
+    if (show || left) && potfile_path.is_none() {
+        return Err("--show and --left need the potfile".into());
+    }
+
+    let generated = matches!(attack, Attack::Patterns | Attack::Keywalk | Attack::Prince);
+    if rules_path.is_some() && attack != Attack::Straight && !generated {
+        return Err("Rules can only be used with a straight wordlist or generated candidates".into());
+    }
+    if walk_min == 0 || walk_min > walk_max {
+        return Err("--walk-min has to be at least 1 and no more than --walk-max".into());
+    }
+    if (prince.skip > 0 || prince.limit.is_some()) && attack != Attack::Prince {
+        return Err("--skip and --limit only work with -a prince".into());
+    }
+    if prince.pw_min > prince.pw_max || prince.elem_cnt_min > prince.elem_cnt_max {
+        return Err("The PRINCE minimums can't be more than the maximums".into());
+    }
+    if (left_rule.is_some() || right_rule.is_some()) && attack != Attack::Combinator {
+        return Err("--rule-left and --rule-right can only be used in combinator mode".into());
+    }
+    if encoding == Encoding::Utf16Le && attack != Attack::Straight {
+        return Err("Only straight attacks can use UTF-16LE wordlists, use another encoding".into());
+    }
+
+    if markov_threshold == 0 || markov_min > markov_max {
+        return Err("--markov-threshold has to be at least 1, and --markov-min no more than --markov-max".into());
+    }
+    if attack == Attack::Markov && markov_hcstat.is_none() && markov_train.is_none() {
+        return Err("-a markov needs a model from --markov-train, given with --markov-hcstat".into());
+    }
+    // Training doesn't need a hashlist, everything on the command line is input
+    let train_inputs = match markov_train {
+        Some(_) if positional.is_empty() => return Err("Nothing to train the Markov model on".into()),
+        Some(_) => std::mem::take(&mut positional),
+        None => Vec::new(),
+    };
+
+    let mut positional = positional.into_iter();
+    let hashes_path = match markov_train {
+        Some(_) => String::new(),
+        None => positional.next().ok_or("Failed to provide hash input file")?,
+    };
+    let mut right_path = None;
+    let (wordlist_path, mask) = match attack {
+        Attack::Straight => (None, None),
+        Attack::Prince => (positional.next(), None),
+        Attack::Combinator => {
+            let left = positional.next();
+            right_path = positional.next();
+            (left, None)
+        }
+        Attack::Mask => (None, positional.next()),
+        Attack::Patterns | Attack::Keywalk | Attack::Markov => (None, None),
+        Attack::HybridWordMask => (positional.next(), positional.next()),
+        Attack::HybridMaskWord => {
+            let mask = positional.next();
+            (positional.next(), mask)
+        }
+    };
+    // Everything else on the command line is another wordlist for a straight attack
+    let wordlists: Vec<String> = match attack {
+        Attack::Straight => positional.collect(),
+        _ => wordlist_path.into_iter().collect(),
+    };
+    if manifest_path.is_some() && attack != Attack::Straight {
+        return Err("--manifest only works with straight attacks".into());
+    }
+    if !show && !left && markov_train.is_none() {
+        let no_wordlist =
+            matches!(attack, Attack::Mask | Attack::Patterns | Attack::Keywalk | Attack::Markov);
+        if wordlists.is_empty() && manifest_path.is_none() && !no_wordlist {
+            return Err("Failed to provide wordlist".into());
+        }
+        if right_path.is_none() && attack == Attack::Combinator {
+            return Err("Failed to provide the right wordlist".into());
+        }
+        let needs_mask = matches!(attack, Attack::Mask | Attack::HybridWordMask | Attack::HybridMaskWord);
+        if mask.is_none() && needs_mask {
+            return Err("Failed to provide mask".into());
+        }
+    }
+    Ok(Options {
+        args: given,
+        attack,
+        hashes_path,
+        wordlists,
+        manifest_path,
+        right_path,
+        separator,
+        left_rule,
+        right_rule,
+        mask,
+        custom_charsets,
+        increment,
+        increment_min,
+        increment_max,
+        encoding,
+        potfile_path,
+        outfile_path,
+        quiet,
+        json,
+        session_path,
+        status_timer,
+        rules_path,
+        loopback,
+        loopback_rules_path,
+        usernames,
+        years,
+        company_path,
+        layouts,
+        walk_min,
+        walk_max,
+        walk_turns,
+        walk_shifts,
+        prince,
+        markov_train,
+        train_inputs,
+        markov_hcstat,
+        markov_threshold,
+        markov_min,
+        markov_max,
+        show,
+        left,
+    })
+}
+/*}}}*/
+
+fn parse_masks(options: &Options) -> Result<Vec<Arc<Mask>>, Box<dyn Error>> {
+    // Turn the mask into the masks to run, one per length with --increment /*{{{*/
+    let mask = Mask::parse(options.mask.as_deref().unwrap(), &options.custom_charsets)?;
+    let lengths = match options.increment {
+        true => {
+            let min = options.increment_min.unwrap_or(1).max(1);
+            let max = options.increment_max.unwrap_or(mask.len()).min(mask.len());
+            min..=max
+        }
+        false => mask.len()..=mask.len(),
+    };
+    let mut masks = Vec::new();
+    for len in lengths {
+        let mask = mask.truncated(len);
+        if mask.keyspace().is_none() {
+            return Err(format!("A mask of length {len} has too many candidates").into());
+        }
+        masks.push(Arc::new(mask));
+    }
+    if masks.is_empty() {
+        return Err("The --increment lengths don't fit the mask".into());
+    }
+    Ok(masks)
+}
+/*}}}*/
+
+fn parse_single_rule(rule: &str) -> Result<Rule, Box<dyn Error>> {
+    // Parse a -j/-k rule given on the command line /*{{{*/
+    rules::parse_rule(rule.as_bytes()).map_err(|e| format!("Bad rule '{rule}': {e}").into())
+}
+/*}}}*/
+
+fn show_hashes<'a>(
+    accounts: impl Iterator<Item = (&'a [u8; 16], &'a Vec<Account>)>,
+    potfile: Option<&Potfile>,
+) {
+    // Print hashes for --show (with their clears) and --left (without) /*{{{*/
+    let mut out: Vec<u8> = Vec::with_capacity(8192);
+    for (hash, users) in accounts {
+        match potfile.and_then(|potfile| potfile.get(hash)) {
+            Some(clear) => {
+                if users.is_empty() {
+                    write_cracked(&mut out, None, hash, clear);
+                }
+                for account in users {
+                    write_cracked(&mut out, Some(account), hash, clear);
+                }
+            }
+            None => {
+                // user:hash or just the hash, both of which we can read back in
+                if users.is_empty() {
+                    writeln!(out, "{}", hex::encode(hash)).unwrap();
+                }
+                for account in users {
+                    account.write_name(&mut out);
+                    writeln!(out, ":{}", hex::encode(hash)).unwrap();
+                }
+            }
+        }
+        if out.len() >= 8192 {
+            stdout().write_all(&out).unwrap();
+            out.clear();
+        }
+    }
+    stdout().write_all(&out).unwrap();
+}
+/*}}}*/
+
 fn main() -> Result<(), Box<dyn Error>> {
     // Put it all together /*{{{*/
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    // Restoring takes the rest of the command line from the session
+    let restored = match args.iter().position(|arg| arg == "--restore") {
+        Some(at) => {
+            args.remove(at);
+            let path = match args.as_slice() {
+                [] => String::from("ntcrack.session"),
+                [flag, path] if flag == "--session" => path.clone(),
+                _ => return Err("--restore can only be given --session".into()),
+            };
+            let session = Session::load(&path)?;
+            info!("[+] Restoring the session from {path}");
+            args = session.args.clone();
+            Some(session)
+        }
+        None => None,
+    };
+    let mut options = parse_args(args)?;
+    QUIET.store(options.quiet, Ordering::Relaxed);
+    if let Some(path) = &options.markov_train {
+        let words = markov::train(&options.train_inputs, path)?;
+        info!("[+] Trained Markov model {path} on {words} words");
+        return Ok(());
+    }
+    // The directories might have changed since, so use what the session read
+    options.wordlists = match &restored {
+        Some(session) => session.wordlists.clone(),
+        None => expand_wordlists(&options.wordlists, options.manifest_path.as_deref())?,
+    };
+    if let Some(session) = &restored {
+        session.check_hashlist(&options.hashes_path)?;
+    }
+    let resume = restored.as_ref().map_or((0, 0), |session| session.position);
+    if restored.is_some() && options.potfile_path.is_none() {
+        info!("[!] The potfile is disabled, so what the session cracked will be looked for again");
+    }
+    if options.wordlists.is_empty() && options.attack == Attack::Straight && !options.show && !options.left {
+        return Err("No wordlists found to read".into());
+    }
 
+    // Load what we've cracked before so we don't do it again
+    let potfile = match &options.potfile_path {
+        Some(path) => load_potfile(path)?,
+        None => Potfile::default(),
+    };
     // Put the input hashes (to be cracked) into the required forms
-    let path = env::args()
-        .nth(1)
-        .expect("Failed to provide hash input file");
-    let hashes = parse_hashes(&path)?;
-    // Build the wordlist (the clears to hash and check for a match)
-    let wordlist_path = env::args().nth(2).expect("Failed to provide wordlist");
+    let (hashes, potted) = parse_hashes(&options.hashes_path, &potfile)?;
+    // The threads share the one copy, and mark off what they crack in it
+    let hashes = Arc::new(hashes);
+    if options.show {
+        show_hashes(potted.iter(), Some(&potfile));
+        return Ok(());
+    }
+    if options.left {
+        let left = hashes.hashlist.iter().map(|(hash, t)| (hash, &t.accounts));
+        show_hashes(left, None);
+        return Ok(());
+    }
+    if hashes.hashlist.is_empty() {
+        info!("[*] All hashes have already been cracked, use --show to see them");
+        return Ok(());
+    }
+    let rules = match &options.rules_path {
+        Some(path) => {
+            let rules = rules::load_rules(path)?;
+            info!("[+] Loaded {} rules from {path}", rules.len());
+            Some(Arc::new(rules))
+        }
+        None => None,
+    };
+    let loopback_rules = match &options.loopback_rules_path {
+        Some(path) => {
+            let rules = rules::load_rules(path)?;
+            info!("[+] Loaded {} loopback rules from {path}", rules.len());
+            Some(Arc::new(rules))
+        }
+        None if options.loopback => rules.clone(),
+        None => None,
+    };
 
     // Do some dd tests to find optimal block size for your HD
     // Here's an example, 1M is repeated to warm the file into cache
@@ -500,49 +2549,219 @@ fn main() -> Result<(), Box<dyn Error>> {
         .parse::<usize>().unwrap();
     */
 
-    let mut wordlist = initialise_wordlist(&wordlist_path, cache_size, block_size)?;
-    let workers = setup_workers(&hashes);
+    let masks = match options.attack {
+        Attack::Mask | Attack::HybridWordMask | Attack::HybridMaskWord => parse_masks(&options)?,
+        _ => Vec::new(),
+    };
+    let markov = match &options.markov_hcstat {
+        Some(path) if options.attack == Attack::Markov => {
+            Some(Arc::new(Markov::load(path, options.markov_threshold)?))
+        }
+        _ => None,
+    };
+    // Or make the clears ourselves
+    let mut generator: Option<Box<dyn Generator>> = match options.attack {
+        Attack::Patterns => {
+            let patterns = patterns::Patterns::new(options.years.clone(), options.company_path.as_deref())?;
+            info!("[+] Patterns make {} candidates", patterns.len());
+            Some(Box::new(patterns))
+        }
+        Attack::Keywalk => {
+            // the names were checked when they were parsed
+            let layouts = options.layouts.iter().filter_map(|l| keywalk::Layout::from_name(l)).collect();
+            let (min, max) = (options.walk_min, options.walk_max);
+            let (turns, shifts) = (options.walk_turns, options.walk_shifts);
+            Some(Box::new(keywalk::Keywalk::new(layouts, min, max, turns, shifts)))
+        }
+        Attack::Prince => {
+            let path = &options.wordlists[0];
+            let prince = prince::Prince::new(path, &options.prince)?;
+            info!(
+                "[+] PRINCE has {} chains making {} candidates",
+                prince.chains(),
+                prince.keyspace()
+            );
+            Some(Box::new(prince))
+        }
+        _ => None,
+    };
+    // The right wordlist is read over and over by every thread, so keep all
+    // of it cached rather than streaming it
+    let combinator = match &options.right_path {
+        Some(path) if is_stream(path) => {
+            return Err("The right wordlist is read over and over, so it can't be streamed".into());
+        }
+        Some(path) if compressed::detect(path)?.is_some() => {
+            return Err("The right wordlist is read over and over, decompress it first".into());
+        }
+        Some(path) => {
+            let right = initialise_wordlist(path, cache_size, block_size, false, 0)?;
+            if right.length > cache_size {
+                info!("[!] The right wordlist is bigger than the cache, this will be slow");
+            }
+            Some(Arc::new(Combinator {
+                right: right.mmap,
+                separator: options.separator.clone().into_bytes(),
+                left_rule: options.left_rule.as_deref().map(parse_single_rule).transpose()?,
+                right_rule: options.right_rule.as_deref().map(parse_single_rule).transpose()?,
+            }))
+        }
+        None => None,
+    };
+    // In the hybrid and combinator modes every word turns into a whole mask
+    // or wordlist's worth of candidates, so use smaller chunks to keep all the
+    // threads busy
+    let expansion: u64 = match (options.attack, &combinator) {
+        (Attack::HybridWordMask | Attack::HybridMaskWord, _) => masks
+            .iter()
+            .try_fold(0_u64, |total, mask| total.checked_add(mask.keyspace()?))
+            .ok_or("The masks have too many candidates between them to put on each word")?,
+        (Attack::Combinator, Some(combinator)) => {
+            combinator.right.iter().filter(|c| **c == 10).count().max(1) as u64
+        }
+        _ => 1,
+    };
+    let chunk_size = match expansion {
+        1 => chunk_size,
+        _ => (chunk_size as u64 / expansion).max(4096) as usize,
+    };
+    catch_signals();
     let start = Instant::now();
-    let mut stats = read_wordlist(&mut wordlist, chunk_size, &workers, &hashes, block_size)?;
-    // All done reading the wordlist, now it's up to the threads to finish
-
-    // Make sure the workers have picked up all the chunks
-    loop {
-        if workers.tx.is_empty() {
-            break;
+    // Accounts using their own name are cheap to find, so do them first
+    let mut username_stats = Stats::default();
+    let mut cracked = match options.usernames {
+        true => crack_usernames(&hashes, &options, &mut username_stats)?,
+        false => Vec::new(),
+    };
+    let mut workers = setup_workers(&hashes, rules.as_ref(), &masks, combinator.as_ref(), &options)?;
+    // What the restored session did counts towards the stats
+    let mut done = restored.as_ref().map_or_else(Stats::default, |session| session.stats);
+    let restored_elapsed = restored.as_ref().map_or(0.0, |session| session.elapsed);
+    done.add(&username_stats);
+    workers.done.set(done);
+    // the first status line's speeds are only for what's done from here on
+    workers.status.last.set((Instant::now(), done.hashed, done.kbs));
+    let mut wordlist_stats = Vec::new();
+    let mut stats = match &mut generator {
+        _ if hashes.found.load(Ordering::Relaxed) == hashes.hashlist.len() => {
+            Stats::default()
         }
-        thread::sleep(std::time::Duration::from_millis(2_u64));
+        _ if options.attack == Attack::Markov => {
+            let lengths = options.markov_min.max(1)..=options.markov_max;
+            read_markov(markov.as_ref().unwrap(), lengths, &workers, &hashes)?
+        }
+        Some(generator) => read_generator(generator.as_mut(), chunk_size, &workers, &hashes)?,
+        None if options.wordlists.is_empty() => read_masks(&masks, &workers, &hashes)?,
+        None => {
+            if let Some(path) = &options.session_path {
+                let (hashlist, hashlist_size) = session::identify(&options.hashes_path);
+                let session = Session {
+                    args: options.args.clone(),
+                    hashlist,
+                    hashlist_size,
+                    wordlists: options.wordlists.clone(),
+                    position: resume,
+                    stats: done,
+                    elapsed: restored_elapsed,
+                };
+                workers.saver = Some(Saver {
+                    path: path.clone(),
+                    session: RefCell::new(session),
+                    saved: Cell::new(Instant::now()),
+                    started: (start, restored_elapsed),
+                });
+                // if it's stopped before a chunk goes out, it's still where it was restored to
+                workers.progress.lock().unwrap().sent = resume;
+            }
+            // streams can be any size, so there's no telling how far through we are
+            if !options.wordlists.iter().any(|path| is_stream(path)) {
+                let sizes: Vec<u64> =
+                    options.wordlists.iter().map(|path| fs::metadata(path).map_or(0, |m| m.len())).collect();
+                let done: u64 = sizes.iter().take(resume.0).sum();
+                let current = resume.1.min(sizes.get(resume.0).copied().unwrap_or(0));
+                let start = (done + current) as f64 / sizes.iter().sum::<u64>().max(1) as f64;
+                workers.status.started = (Instant::now(), start);
+                workers.status.sizes = sizes;
+            }
+            let sizes = (chunk_size, cache_size, block_size);
+            wordlist_stats = read_wordlists(&options, sizes, &workers, &hashes, resume)?;
+            // from here on the stats being kept are for all the wordlists
+            workers.done.set(done);
+            let mut stats = Stats::default();
+            wordlist_stats.iter().for_each(|s| stats.add(s));
+            stats
+        }
+    };
+    if interrupted() {
+        info!("[!] Interrupted, stopping the threads, do it again to quit straight away");
     }
-    // tell the threads to exit, as many times as there are threads
-    for _ in 0..workers.threadnum {
-        workers.tx.send(None)?;
+    // All done reading the wordlist, now it's up to the threads to finish
+    let cracked_from = Arc::clone(&workers.cracked_from);
+    let saved = workers.saver.as_ref().map(|saver| saver.path.clone());
+    cracked.extend(finish_workers(workers, &mut stats)?);
+    stats.add(&done);
+    // We got to the end, so there's nothing to restore
+    if let Some(path) = saved.filter(|path| !interrupted() && Path::new(path).exists()) {
+        fs::remove_file(path)?;
     }
-    // wait for threads to exit
-    // Don't try put this in a function JoinHandle<()> doesn't implement Copy
-    for thread in workers.threadhand {
-        thread.join().unwrap();
+    if options.wordlists.len() > 1 {
+        info!("[+] Wordlist stats:");
+        for (i, path) in options.wordlists.iter().enumerate() {
+            match wordlist_stats.get(i) {
+                Some(_) if i < resume.0 => info!("  {path}: done before the restore"),
+                None if interrupted() => info!("  {path}: not started before the interrupt"),
+                Some(read) => info!(
+                    "  {path}: Read: {} kB, Cracked: {}",
+                    read.kbs,
+                    cracked_from[i].load(Ordering::Relaxed)
+                ),
+                None => info!("  {path}: not needed, everything was cracked"),
+            }
+        }
     }
 
-    // get final numbers
-    while let Ok(recv_stats) = workers.rx2.try_recv() {
-        stats.cracked += recv_stats.cracked;
-        stats.hashed += recv_stats.hashed;
-        stats.waits += recv_stats.waits;
+    if let Some(rules) = &loopback_rules {
+        // Keep mangling what we crack until it stops cracking anything new
+        // Loopback clears are printable UTF-8, whatever the wordlist was in
+        let loopback_options = Options {
+            attack: Attack::Straight,
+            encoding: match options.encoding {
+                Encoding::Utf16Le => Encoding::Utf8,
+                encoding => encoding,
+            },
+            ..options.clone()
+        };
+        let mut tried: HashSet<Vec<u8>> = HashSet::new();
+        let mut pass = 1;
+        while hashes.found.load(Ordering::Relaxed) < hashes.hashlist.len() && !interrupted() {
+            let mut clears = std::mem::take(&mut cracked);
+            clears.retain(|clear| tried.insert(clear.clone()));
+            if clears.is_empty() {
+                break;
+            }
+            info!("[+] Loopback pass {pass}, {} new clears through {} rules", clears.len(), rules.len());
+            let workers = setup_workers(&hashes, Some(rules), &[], None, &loopback_options)?;
+            let chunk_size = (chunk_size / rules.len()).max(4096);
+            stats.add(&read_clears(&clears, chunk_size, &workers, &hashes)?);
+            cracked = finish_workers(workers, &mut stats)?;
+            pass += 1;
+        }
     }
 
-    // calculate performance stats
+    // calculate performance stats, over the time the restored session took too
     let elapsed = (start.elapsed().as_secs() as f64)
-        + (f64::from(start.elapsed().subsec_nanos()) / 1_000_000_000.0);
+        + (f64::from(start.elapsed().subsec_nanos()) / 1_000_000_000.0)
+        + restored_elapsed;
     //safe usize->f64 conversion checks
     assert!(stats.hashed <= f64::MAX as usize);
     assert!(stats.kbs <= f64::MAX as usize);
     assert!(stats.waits <= f64::MAX as usize);
-    print!(
+    info!(
         "[+] Stats:
   Time: {:.2} s
   Hashed: {}, Cracked: {}, Crack Speed: {:.2} kH/s
   Read: {} kB, Read Speed: {:.2} MB/s
-  Thread Waits: {} Wait Speed: {:.2} w/s\n",
+  Thread Waits: {} Wait Speed: {:.2} w/s",
         elapsed,
         stats.hashed,
         stats.cracked,
@@ -552,7 +2771,99 @@ fn main() -> Result<(), Box<dyn Error>> {
         stats.waits,
         stats.waits as f64 / elapsed
     );
+    if stats.compressed_kbs > 0 {
+        info!(
+            "  Compressed Read: {} kB, Compressed Read Speed: {:.2} MB/s",
+            stats.compressed_kbs,
+            (stats.compressed_kbs as f64 / elapsed) / 1024_f64
+        );
+    }
+    if options.json {
+        // the stats go after the results, so whatever reads them gets the lot
+        let mut out: Vec<u8> = vec![b'{'];
+        json::write_field(&mut out, "type", "stats");
+        json::write_number(&mut out, "time", format_args!("{elapsed:.2}"));
+        json::write_number(&mut out, "hashed", stats.hashed);
+        json::write_number(&mut out, "cracked", stats.cracked);
+        json::write_number(&mut out, "waits", stats.waits);
+        json::write_number(&mut out, "kbs", stats.kbs);
+        json::write_number(&mut out, "compressed_kbs", stats.compressed_kbs);
+        json::write_bool(&mut out, "interrupted", interrupted());
+        out.extend_from_slice(b"}\n");
+        match &options.outfile_path {
+            Some(path) => OpenOptions::new().create(true).append(true).open(path)?.write_all(&out)?,
+            None => stdout().write_all(&out)?,
+        }
+    }
+    // so scripts can tell a run that was stopped from one that finished
+    if interrupted() {
+        stdout().flush()?;
+        std::process::exit(2);
+    }
 
     Ok(())
 }
 /*}}}*/
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HASH: &str = "8846f7eaee8fb117ad06bdd830b7586c";
+
+    fn hash() -> [u8; 16] {
+        <[u8; 16]>::from_hex(HASH).unwrap()
+    }
+
+    #[test]
+    fn bare_and_john_hashes() {
+        let (format, raw, account) = parse_hash_line(HASH).unwrap();
+        assert!(format == HashFormat::Bare && raw == hash() && account.is_none());
+        let (format, raw, _) = parse_hash_line(&HASH.to_uppercase()).unwrap();
+        assert!(format == HashFormat::Bare && raw == hash());
+        let (format, raw, account) = parse_hash_line(&format!("$NT${HASH}")).unwrap();
+        assert!(format == HashFormat::John && raw == hash() && account.is_none());
+    }
+
+    #[test]
+    fn potfile_lines() {
+        // the clear can have colons in it
+        let (format, raw, account) = parse_hash_line(&format!("{HASH}:pass:word")).unwrap();
+        assert!(format == HashFormat::Potfile && raw == hash() && account.is_none());
+    }
+
+    #[test]
+    fn user_lines() {
+        let (format, raw, account) = parse_hash_line(&format!("alice:{HASH}")).unwrap();
+        let account = account.unwrap();
+        assert!(format == HashFormat::User && raw == hash());
+        assert!(account.username == "alice" && account.domain.is_none());
+        let (format, raw, account) = parse_hash_line(&format!("CORP\\bob:$NT${HASH}:::")).unwrap();
+        let account = account.unwrap();
+        assert!(format == HashFormat::User && raw == hash());
+        assert!(account.username == "bob" && account.domain.as_deref() == Some("CORP"));
+    }
+
+    #[test]
+    fn pwdump_lines() {
+        let line = format!("CORP\\alice:1104:aad3b435b51404eeaad3b435b51404ee:{HASH}::: (status=Enabled)");
+        let (format, raw, account) = parse_hash_line(&line).unwrap();
+        let account = account.unwrap();
+        assert!(format == HashFormat::Pwdump && raw == hash());
+        assert!(account.username == "alice" && account.domain.as_deref() == Some("CORP"));
+        assert!(account.history.is_none() && account.enabled == Some(true));
+        let line = format!("alice_history2:1104:aad3b435b51404eeaad3b435b51404ee:{HASH}::: (status=Disabled)");
+        let account = parse_hash_line(&line).unwrap().2.unwrap();
+        assert!(account.username == "alice" && account.domain.is_none());
+        assert!(account.history == Some(2) && account.enabled == Some(false));
+    }
+
+    #[test]
+    fn bad_lines() {
+        assert!(parse_hash_line("").is_none());
+        assert!(parse_hash_line("not a hash").is_none());
+        assert!(parse_hash_line(&HASH[1..]).is_none());
+        assert!(parse_hash_line("alice:nothex").is_none());
+        assert!(parse_hash_line(&format!("alice:1104:{HASH}")).is_none());
+    }
+}
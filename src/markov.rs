@@ -0,0 +1,304 @@
+// Per position Markov statistics, so brute force tries likely strings first
+
+use crate::encoding;
+use std::error::Error;
+use std::fs;
+use std::io::Write;
+
+// Positions past this share the stats of the last one, like hashcat's
+// .hcstat there's not much to learn about the 30th character
+const MAX_POS: usize = 32;
+
+struct Counts {
+    // What training counts up /*{{{*/
+    first: Vec<u64>,          // [char], how often each character starts a word
+    next: Vec<Vec<Vec<u64>>>, // [pos][prev][char], how often char follows prev at pos
+}
+/*}}}*/
+
+impl Counts {
+    fn new() -> Counts {
+        Counts {
+            first: vec![0; 256],
+            next: vec![vec![vec![0; 256]; 256]; MAX_POS],
+        }
+    }
+
+    fn add(&mut self, word: &[u8]) {
+        // Count up the characters in one training word /*{{{*/
+        let Some(first) = word.first() else {
+            return;
+        };
+        self.first[*first as usize] += 1;
+        for (pos, pair) in word.windows(2).enumerate() {
+            let pos = (pos + 1).min(MAX_POS - 1);
+            self.next[pos][pair[0] as usize][pair[1] as usize] += 1;
+        }
+    }
+    /*}}}*/
+}
+
+pub fn train(inputs: &[String], path: &str) -> Result<usize, Box<dyn Error>> {
+    // Count up potfile clears & wordlist words and save them as a model /*{{{*/
+    let mut counts = Counts::new();
+    let mut words = 0;
+    let mut unhexed: Vec<u8> = Vec::new();
+    for input in inputs {
+        let contents = fs::read(input)?;
+        for line in contents.split(|c| *c == 10) {
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            // potfile lines are hash:clear, we only want the clear
+            let line = match line.get(32) {
+                Some(b':') if line[..32].iter().all(u8::is_ascii_hexdigit) => &line[33..],
+                _ => line,
+            };
+            let word = encoding::unhex(line, &mut unhexed);
+            if !word.is_empty() {
+                counts.add(word);
+                words += 1;
+            }
+        }
+    }
+    if words == 0 {
+        return Err("Nothing to train the Markov model on".into());
+    }
+
+    // A line per non-zero count, it's sparse enough that this is small
+    let mut out: Vec<u8> = Vec::new();
+    writeln!(out, "# ntcrack markov stats v1")?;
+    for (c, count) in counts.first.iter().enumerate().filter(|(_, n)| **n > 0) {
+        writeln!(out, "first {c} {count}")?;
+    }
+    for (pos, prevs) in counts.next.iter().enumerate() {
+        for (prev, chars) in prevs.iter().enumerate() {
+            for (c, count) in chars.iter().enumerate().filter(|(_, n)| **n > 0) {
+                writeln!(out, "next {pos} {prev} {c} {count}")?;
+            }
+        }
+    }
+    fs::write(path, out)?;
+    Ok(words)
+}
+/*}}}*/
+
+fn load_counts(path: &str) -> Result<Counts, Box<dyn Error>> {
+    // Read a model written by train() /*{{{*/
+    let contents = fs::read_to_string(path)?;
+    let mut counts = Counts::new();
+    for (lineno, line) in contents.lines().enumerate() {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let bad = || format!("Bad line {} in Markov model {path}", lineno + 1);
+        let fields: Vec<&str> = line.split(' ').collect();
+        let numbers: Vec<u64> = fields[1..]
+            .iter()
+            .map(|f| f.parse::<u64>())
+            .collect::<Result<_, _>>()
+            .map_err(|_| bad())?;
+        match (fields[0], numbers.as_slice()) {
+            ("first", &[c, count]) if c < 256 => counts.first[c as usize] = count,
+            ("next", &[pos, prev, c, count]) if (pos as usize) < MAX_POS && prev < 256 && c < 256 => {
+                counts.next[pos as usize][prev as usize][c as usize] = count;
+            }
+            _ => return Err(bad().into()),
+        }
+    }
+    Ok(counts)
+}
+/*}}}*/
+
+pub struct Markov {
+    // The characters to try at each position, likeliest first /*{{{*/
+    first: Vec<u8>,             // [rank]
+    next: Vec<Vec<Vec<u8>>>,    // [pos][prev][rank]
+    base: usize,                // how many characters each position gets
+}
+/*}}}*/
+
+impl Markov {
+    pub fn load(path: &str, threshold: usize) -> Result<Markov, Box<dyn Error>> {
+        // Turn a model into per position orderings, cut off at threshold /*{{{*/
+        let counts = load_counts(path)?;
+        // only characters that turned up in training get used, ones that
+        // never followed a particular character go after the ones that did
+        let mut overall = counts.first.clone();
+        for prevs in &counts.next {
+            for chars in prevs {
+                for (c, count) in chars.iter().enumerate() {
+                    overall[c] += count;
+                }
+            }
+        }
+        let mut alphabet: Vec<u8> = (0..=255).filter(|c| overall[*c as usize] > 0).collect();
+        if alphabet.is_empty() {
+            return Err(format!("Markov model {path} is empty").into());
+        }
+        alphabet.sort_by_key(|c| std::cmp::Reverse(overall[*c as usize]));
+        let base = threshold.min(alphabet.len());
+
+        let ranked = |counts: &[u64]| -> Vec<u8> {
+            let mut chars = alphabet.clone();
+            // a stable sort keeps the overall order for ties
+            chars.sort_by_key(|c| std::cmp::Reverse(counts[*c as usize]));
+            chars.truncate(base);
+            chars
+        };
+        let first = ranked(&counts.first);
+        let next = counts
+            .next
+            .iter()
+            .map(|prevs| prevs.iter().map(|chars| ranked(chars)).collect())
+            .collect();
+        Ok(Markov { first, next, base })
+    }
+    /*}}}*/
+
+    pub fn keyspace(&self, len: usize) -> Option<u64> {
+        // How many candidates of a length there are, None if it's too many to count
+        (self.base as u64).checked_pow(len as u32)
+    }
+
+    fn pick(&self, candidate: &mut [u8], ranks: &[usize], from: usize) {
+        // Fill in the characters from a position on, each depends on the last /*{{{*/
+        for pos in from..candidate.len() {
+            candidate[pos] = match pos {
+                0 => self.first[ranks[0]],
+                _ => self.next[pos.min(MAX_POS - 1)][candidate[pos - 1] as usize][ranks[pos]],
+            };
+        }
+    }
+    /*}}}*/
+
+    pub fn for_each(&self, len: usize, start: u64, end: u64, mut f: impl FnMut(&[u8])) {
+        // Call f with each candidate in the start..end slice of a length's keyspace /*{{{*/
+        // Like a mask, but each position is the rank of the character given
+        // the one before it, so the likeliest come first
+        let base = self.base as u64;
+        let mut ranks = vec![0_usize; len];
+        let mut index = start;
+        for rank in ranks.iter_mut().rev() {
+            *rank = (index % base) as usize;
+            index /= base;
+        }
+        let mut candidate = vec![0_u8; len];
+        self.pick(&mut candidate, &ranks, 0);
+
+        for _ in start..end {
+            f(&candidate);
+            // tick over to the next ranks, then redo everything after the
+            // leftmost one that changed
+            let mut changed = len;
+            for (pos, rank) in ranks.iter_mut().enumerate().rev() {
+                *rank += 1;
+                changed = pos;
+                if *rank < self.base {
+                    break;
+                }
+                *rank = 0;
+            }
+            self.pick(&mut candidate, &ranks, changed);
+        }
+    }
+    /*}}}*/
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp(name: &str) -> String {
+        let dir = std::env::temp_dir();
+        dir.join(format!("ntcrack-{}-{name}", std::process::id())).display().to_string()
+    }
+
+    fn trained(name: &str) -> Markov {
+        // b follows a in every word, and c follows b more than d or e do
+        let wordlist = temp(&format!("{name}.txt"));
+        let potfile = temp(&format!("{name}.pot"));
+        let model = temp(&format!("{name}.stat"));
+        fs::write(&wordlist, "abc\r\nabd\n$HEX[616263]\n\n").unwrap();
+        fs::write(&potfile, "8846f7eaee8fb117ad06bdd830b7586c:abe\n").unwrap();
+        let words = train(&[wordlist.clone(), potfile.clone()], &model).unwrap();
+        assert_eq!(words, 4);
+        let counts = load_counts(&model).unwrap();
+        let markov = Markov::load(&model, 10).unwrap();
+        for path in [wordlist, potfile, model] {
+            fs::remove_file(path).unwrap();
+        }
+        assert_eq!(counts.first[b'a' as usize], 4);
+        assert_eq!(counts.first.iter().sum::<u64>(), 4);
+        assert_eq!(counts.next[1][b'a' as usize][b'b' as usize], 4);
+        assert_eq!(counts.next[2][b'b' as usize][b'c' as usize], 2);
+        assert_eq!(counts.next[2][b'b' as usize][b'd' as usize], 1);
+        assert_eq!(counts.next[2][b'b' as usize][b'e' as usize], 1);
+        markov
+    }
+
+    fn candidates(markov: &Markov, len: usize, start: u64, end: u64) -> Vec<Vec<u8>> {
+        let mut all = Vec::new();
+        markov.for_each(len, start, end, |candidate| all.push(candidate.to_vec()));
+        all
+    }
+
+    #[test]
+    fn train_then_load() {
+        let markov = trained("round-trip");
+        // only the characters it was trained on, most common first
+        assert_eq!(markov.base, 5);
+        assert_eq!(markov.first, b"abcde");
+        assert_eq!(markov.next[2][b'b' as usize][0], b'c');
+        assert_eq!(markov.keyspace(3), Some(125));
+        assert_eq!(markov.keyspace(40), None);
+    }
+
+    #[test]
+    fn likeliest_first() {
+        let markov = trained("likeliest");
+        let all = candidates(&markov, 3, 0, markov.keyspace(3).unwrap());
+        assert_eq!(all[0], b"abc");
+        assert_eq!(all[1], b"abd");
+        assert_eq!(all[2], b"abe");
+    }
+
+    #[test]
+    fn slices_join_up() {
+        let markov = trained("slices");
+        let keyspace = markov.keyspace(4).unwrap();
+        let all = candidates(&markov, 4, 0, keyspace);
+        assert_eq!(all.len() as u64, keyspace);
+        let mut unique = all.clone();
+        unique.sort();
+        unique.dedup();
+        assert_eq!(unique.len(), all.len());
+        let mut joined = Vec::new();
+        for (start, end) in [(0, 1), (1, 7), (7, 256), (256, keyspace)] {
+            joined.extend(candidates(&markov, 4, start, end));
+        }
+        assert_eq!(joined, all);
+    }
+
+    #[test]
+    fn bad_fields_are_errors() {
+        let path = temp("bad-fields");
+        for bad in [
+            "first 256 1",
+            "first 97",
+            "first a 1",
+            "next 32 97 98 1",
+            "next 1 256 98 1",
+            "next 1 97 256 1",
+            "next 1 97 98 -1",
+            "last 1 2",
+        ] {
+            fs::write(&path, format!("# ntcrack markov stats v1\n{bad}\n")).unwrap();
+            let err = load_counts(&path).err().unwrap().to_string();
+            assert!(err.starts_with("Bad line 2 in Markov model"), "{bad}: {err}");
+        }
+        fs::write(&path, "first 255 1\nnext 31 97 255 2\n").unwrap();
+        let counts = load_counts(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(counts.first[255], 1);
+        assert_eq!(counts.next[31][97][255], 2);
+    }
+}
@@ -0,0 +1,220 @@
+// Hashcat style masks for brute forcing, e.g. ?u?l?l?l?d?d
+
+use std::ops::ControlFlow;
+
+pub struct Mask {
+    charsets: Vec<Vec<u8>>, // the characters to try at each position
+}
+
+fn builtin(c: u8) -> Option<Vec<u8>> {
+    // The ?x charsets hashcat has built in /*{{{*/
+    let charset: Vec<u8> = match c {
+        b'l' => (b'a'..=b'z').collect(),
+        b'u' => (b'A'..=b'Z').collect(),
+        b'd' => (b'0'..=b'9').collect(),
+        b'h' => (b'0'..=b'9').chain(b'a'..=b'f').collect(),
+        b'H' => (b'0'..=b'9').chain(b'A'..=b'F').collect(),
+        b's' => (b' '..=b'~').filter(|c| !c.is_ascii_alphanumeric()).collect(),
+        b'a' => (b' '..=b'~').collect(),
+        b'b' => (0..=255).collect(),
+        _ => return None,
+    };
+    Some(charset)
+}
+/*}}}*/
+
+fn parse_charset(charset: &str, custom: &[Vec<u8>]) -> Result<Vec<Vec<u8>>, String> {
+    // Turn a mask or custom charset into the characters for each position /*{{{*/
+    let mut positions = Vec::new();
+    let mut bytes = charset.bytes();
+    while let Some(c) = bytes.next() {
+        if c != b'?' {
+            positions.push(vec![c]);
+            continue;
+        }
+        let c = bytes.next().ok_or("mask can't end with a lone ?")?;
+        let charset = match c {
+            b'?' => vec![b'?'],
+            b'1'..=b'4' => custom
+                .get((c - b'1') as usize)
+                .filter(|charset| !charset.is_empty())
+                .ok_or(format!("custom charset ?{} wasn't given", c as char))?
+                .clone(),
+            _ => builtin(c).ok_or(format!("unknown charset ?{}", c as char))?,
+        };
+        positions.push(charset);
+    }
+    Ok(positions)
+}
+/*}}}*/
+
+impl Mask {
+    pub fn parse(mask: &str, custom: &[Option<String>; 4]) -> Result<Mask, String> {
+        // Parse a mask, with up to four custom charsets for ?1 to ?4 /*{{{*/
+        // custom charsets can use the built in ones, but not each other
+        let mut charsets = Vec::new();
+        for charset in custom {
+            let mut chars: Vec<u8> = match charset {
+                Some(charset) => parse_charset(charset, &[])?.concat(),
+                None => Vec::new(),
+            };
+            // no point trying the same thing twice
+            let mut seen = [false; 256];
+            chars.retain(|c| !std::mem::replace(&mut seen[*c as usize], true));
+            charsets.push(chars);
+        }
+        let charsets = parse_charset(mask, &charsets)?;
+        if charsets.is_empty() {
+            return Err("empty mask".to_string());
+        }
+        Ok(Mask { charsets })
+    }
+    /*}}}*/
+
+    pub fn len(&self) -> usize {
+        self.charsets.len()
+    }
+
+    pub fn truncated(&self, len: usize) -> Mask {
+        // The first len positions of the mask, for --increment /*{{{*/
+        Mask {
+            charsets: self.charsets[..len].to_vec(),
+        }
+    }
+    /*}}}*/
+
+    pub fn keyspace(&self) -> Option<u64> {
+        // How many candidates the mask makes, None if it's too many to count /*{{{*/
+        self.charsets
+            .iter()
+            .try_fold(1_u64, |total, charset| total.checked_mul(charset.len() as u64))
+    }
+    /*}}}*/
+
+    pub fn for_each(
+        &self,
+        start: u64,
+        end: u64,
+        mut f: impl FnMut(&[u8]) -> ControlFlow<()>,
+    ) -> ControlFlow<()> {
+        // Call f with each candidate in the start..end slice of the keyspace /*{{{*/
+        // The last position changes fastest, like an odometer, and f can
+        // break off part way through
+        let mut digits = vec![0_usize; self.len()];
+        let mut index = start;
+        for (digit, charset) in digits.iter_mut().zip(&self.charsets).rev() {
+            let base = charset.len() as u64;
+            *digit = (index % base) as usize;
+            index /= base;
+        }
+        let mut candidate: Vec<u8> = digits
+            .iter()
+            .zip(&self.charsets)
+            .map(|(digit, charset)| charset[*digit])
+            .collect();
+
+        for _ in start..end {
+            f(&candidate)?;
+            // tick over to the next candidate, carrying as we go
+            for ((digit, charset), c) in digits
+                .iter_mut()
+                .zip(&self.charsets)
+                .zip(candidate.iter_mut())
+                .rev()
+            {
+                *digit += 1;
+                if *digit < charset.len() {
+                    *c = charset[*digit];
+                    break;
+                }
+                *digit = 0;
+                *c = charset[0];
+            }
+        }
+        ControlFlow::Continue(())
+    }
+    /*}}}*/
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    fn custom(charsets: [Option<&str>; 4]) -> [Option<String>; 4] {
+        charsets.map(|charset| charset.map(String::from))
+    }
+
+    fn candidates(mask: &Mask, start: u64, end: u64) -> Vec<Vec<u8>> {
+        let mut all = Vec::new();
+        let _ = mask.for_each(start, end, |candidate| {
+            all.push(candidate.to_vec());
+            ControlFlow::Continue(())
+        });
+        all
+    }
+
+    #[test]
+    fn custom_charsets_use_the_built_in_ones() {
+        let mask = Mask::parse("?1?2", &custom([Some("?dx"), Some("?u?l"), None, None])).unwrap();
+        assert_eq!(mask.charsets[0], b"0123456789x");
+        assert_eq!(mask.charsets[1].len(), 52);
+        assert_eq!(mask.keyspace(), Some(11 * 52));
+        // but not each other
+        assert!(Mask::parse("?1", &custom([Some("?2"), Some("ab"), None, None])).is_err());
+        assert!(Mask::parse("?3", &custom([Some("ab"), None, None, None])).is_err());
+    }
+
+    #[test]
+    fn duplicates_are_dropped() {
+        let mask = Mask::parse("?1", &custom([Some("aba?l"), None, None, None])).unwrap();
+        assert_eq!(mask.keyspace(), Some(26));
+        assert_eq!(&mask.charsets[0][..3], b"abc");
+    }
+
+    #[test]
+    fn literals_and_question_marks() {
+        let mask = Mask::parse("a??b?d", &custom([None; 4])).unwrap();
+        assert_eq!(mask.len(), 4);
+        assert_eq!(candidates(&mask, 0, 1), [b"a?b0"]);
+        assert!(Mask::parse("abc?", &custom([None; 4])).is_err());
+        assert!(Mask::parse("?x", &custom([None; 4])).is_err());
+        assert!(Mask::parse("", &custom([None; 4])).is_err());
+    }
+
+    #[test]
+    fn slices_cover_the_keyspace_once() {
+        let mask = Mask::parse("?1?d?1", &custom([Some("abc"), None, None, None])).unwrap();
+        let keyspace = mask.keyspace().unwrap();
+        assert_eq!(keyspace, 90);
+        let whole = candidates(&mask, 0, keyspace);
+        assert_eq!(whole.first().unwrap(), b"a0a");
+        assert_eq!(whole.last().unwrap(), b"c9c");
+        assert_eq!(whole.iter().collect::<HashSet<_>>().len(), 90);
+        // any way of slicing it up gives the same candidates in the same order
+        for slice in [1, 7, 13, 89, 90] {
+            let mut joined = Vec::new();
+            let mut start = 0;
+            while start < keyspace {
+                let end = keyspace.min(start + slice);
+                joined.extend(candidates(&mask, start, end));
+                start = end;
+            }
+            assert_eq!(joined, whole);
+        }
+    }
+
+    #[test]
+    fn stops_when_told() {
+        let mask = Mask::parse("?d?d", &custom([None; 4])).unwrap();
+        let mut seen = 0;
+        let flow = mask.for_each(0, 100, |_| {
+            seen += 1;
+            match seen {
+                5 => ControlFlow::Break(()),
+                _ => ControlFlow::Continue(()),
+            }
+        });
+        assert!(flow.is_break() && seen == 5);
+    }
+}
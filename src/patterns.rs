@@ -0,0 +1,170 @@
+// The Spring2025! and Welcome01 style passwords corporate password policies breed
+
+use crate::Generator;
+use std::error::Error;
+use std::fs;
+use std::ops::RangeInclusive;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// English, German, French, Spanish and Dutch
+const SEASONS: &[&str] = &[
+    "Spring", "Summer", "Autumn", "Fall", "Winter", "Fruehling", "Frühling", "Sommer", "Herbst",
+    "Printemps", "Ete", "Été", "Automne", "Hiver", "Primavera", "Verano", "Otono", "Otoño",
+    "Invierno", "Lente", "Zomer", "Herfst",
+];
+
+const MONTHS: &[&str] = &[
+    "January", "February", "March", "April", "May", "June", "July", "August", "September",
+    "October", "November", "December", "Jan", "Feb", "Mar", "Apr", "Jun", "Jul", "Aug", "Sep",
+    "Sept", "Oct", "Nov", "Dec", "Januar", "Februar", "Maerz", "März", "Juni", "Juli", "Oktober",
+    "Dezember", "Janvier", "Fevrier", "Février", "Mars", "Avril", "Mai", "Juin", "Juillet", "Aout",
+    "Août", "Septembre", "Octobre", "Novembre", "Decembre", "Décembre", "Enero", "Febrero",
+    "Marzo", "Abril", "Mayo", "Junio", "Julio", "Agosto", "Septiembre", "Octubre", "Noviembre",
+    "Diciembre", "Januari", "Februari", "Maart", "Mei", "Augustus",
+];
+
+const WEEKDAYS: &[&str] = &[
+    "Monday", "Tuesday", "Wednesday", "Thursday", "Friday", "Saturday", "Sunday", "Montag",
+    "Dienstag", "Mittwoch", "Donnerstag", "Freitag", "Samstag", "Sonntag", "Lundi", "Mardi",
+    "Mercredi", "Jeudi", "Vendredi", "Samedi", "Dimanche", "Lunes", "Martes", "Miercoles",
+    "Miércoles", "Jueves", "Viernes", "Sabado", "Sábado", "Domingo", "Maandag", "Dinsdag",
+    "Woensdag", "Donderdag", "Vrijdag", "Zaterdag", "Zondag",
+];
+
+// What helpdesks set and users never change
+const BASES: &[&str] = &[
+    "Welcome", "Password", "Passw0rd", "P@ssw0rd", "P@ssword", "Changeme", "Change", "Letmein",
+    "Secret", "Admin", "Qwerty", "Hello", "Willkommen", "Passwort", "Bienvenue", "Bienvenido",
+    "Welkom", "Wachtwoord",
+];
+
+// Numbers that aren't years people stick in the middle
+const NUMBERS: &[&str] = &["", "1", "01", "12", "123", "1234", "2", "02", "007"];
+
+// Between the word and the number
+const SEPARATORS: &[&str] = &["", "@", "#", "_", "-", ".", " ", "!", "$"];
+
+// On the very end to please the complexity rules
+const SUFFIXES: &[&str] = &["", "!", "!!", "?", "*", "$", "#", "@", ".", "1", "!1"];
+
+pub fn recent_years() -> RangeInclusive<u32> {
+    // The last ten years and next year, for Name2024 style passwords /*{{{*/
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    // close enough, it's only off for a few hours around new year
+    let year = 1970 + (secs / 31_556_952) as u32;
+    year - 10..=year + 1
+}
+/*}}}*/
+
+pub fn parse_years(range: &str) -> Result<RangeInclusive<u32>, String> {
+    // Parse a 2015-2026 or 2024 style year range /*{{{*/
+    let bad = || format!("Bad year range {range}, use e.g. 2015-2026");
+    let (from, to) = range.split_once('-').unwrap_or((range, range));
+    let from: u32 = from.trim().parse().map_err(|_| bad())?;
+    let to: u32 = to.trim().parse().map_err(|_| bad())?;
+    if from > to || to > 9999 {
+        return Err(bad());
+    }
+    Ok(from..=to)
+}
+/*}}}*/
+
+pub struct Patterns {
+    words: Vec<String>,
+    numbers: Vec<String>, // years and NUMBERS
+    next: u64,            // index of the next word/separator/number/suffix to make
+    total: u64,
+}
+
+impl Patterns {
+    pub fn new(years: RangeInclusive<u32>, company: Option<&str>) -> Result<Patterns, Box<dyn Error>> {
+        // Build the word & number lists, with the company words from a file /*{{{*/
+        let mut bases: Vec<String> = Vec::new();
+        if let Some(path) = company {
+            let contents = fs::read_to_string(path)?;
+            let company: Vec<&str> = contents
+                .lines()
+                .map(str::trim)
+                .filter(|l| !l.is_empty() && !l.starts_with('#'))
+                .collect();
+            if company.is_empty() {
+                return Err(format!("No company words found in {path}").into());
+            }
+            bases.extend(company.iter().map(|w| w.to_string()));
+        }
+        bases.extend(
+            [SEASONS, MONTHS, WEEKDAYS, BASES]
+                .concat()
+                .iter()
+                .map(|w| w.to_string()),
+        );
+        // as given, capitalised, then all lower and all upper case, without repeats
+        let mut words: Vec<String> = Vec::new();
+        for base in &bases {
+            let mut chars = base.chars();
+            let capitalised: String = match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars.as_str().to_lowercase().chars()).collect(),
+                None => continue,
+            };
+            for word in [base.clone(), capitalised, base.to_lowercase(), base.to_uppercase()] {
+                if !words.contains(&word) {
+                    words.push(word);
+                }
+            }
+        }
+        let mut numbers: Vec<String> = NUMBERS.iter().map(|n| n.to_string()).collect();
+        for year in years {
+            for number in [format!("{year}"), format!("{:02}", year % 100)] {
+                if !numbers.contains(&number) {
+                    numbers.push(number);
+                }
+            }
+        }
+        let total = (words.len() * SEPARATORS.len() * numbers.len() * SUFFIXES.len()) as u64;
+        Ok(Patterns {
+            words,
+            numbers,
+            next: 0,
+            total,
+        })
+    }
+    /*}}}*/
+
+    pub fn len(&self) -> u64 {
+        // How many candidates we make, a separator is only used before a number
+        let numbered = (self.numbers.len() - 1) * SEPARATORS.len() + 1;
+        (self.words.len() * numbered * SUFFIXES.len()) as u64
+    }
+}
+
+impl Generator for Patterns {
+    fn fill(&mut self, chunk: &mut Vec<u8>, size: usize) -> bool {
+        // Make the next candidates, word + separator + number + suffix /*{{{*/
+        while chunk.len() < size && self.next < self.total {
+            // the suffix changes fastest, then the number, separator and word
+            let mut i = self.next as usize;
+            self.next += 1;
+            let suffix = SUFFIXES[i % SUFFIXES.len()];
+            i /= SUFFIXES.len();
+            let number = &self.numbers[i % self.numbers.len()];
+            i /= self.numbers.len();
+            let separator = SEPARATORS[i % SEPARATORS.len()];
+            i /= SEPARATORS.len();
+            let word = &self.words[i];
+            // a separator with nothing after it is just another suffix
+            if number.is_empty() && !separator.is_empty() {
+                continue;
+            }
+            chunk.extend_from_slice(word.as_bytes());
+            chunk.extend_from_slice(separator.as_bytes());
+            chunk.extend_from_slice(number.as_bytes());
+            chunk.extend_from_slice(suffix.as_bytes());
+            chunk.push(10);
+        }
+        self.next < self.total
+    }
+    /*}}}*/
+}
@@ -0,0 +1,251 @@
+// PRINCE, chaining words from a wordlist together into longer candidates
+
+use crate::compressed;
+use crate::encoding;
+use crate::Generator;
+use std::collections::HashSet;
+use std::error::Error;
+use std::fs;
+use std::io::Read;
+
+#[derive(Clone)]
+pub struct Settings {
+    pub pw_min: usize,       // shortest candidate, in bytes
+    pub pw_max: usize,       // longest candidate
+    pub elem_cnt_min: usize, // fewest words in a chain
+    pub elem_cnt_max: usize, // most words in a chain
+    pub skip: u128,          // how many candidates to skip at the start
+    pub limit: Option<u128>, // how many candidates to make after that
+}
+
+struct Chain {
+    lens: Vec<usize>, // the length of each word in the chain
+    keyspace: u128,
+}
+
+pub struct Prince {
+    words: Vec<Vec<Vec<u8>>>, // the words of each length, in wordlist order
+    chains: Vec<Chain>,       // smallest keyspace first
+    chain: usize,             // the chain we're working through
+    next: u128,               // and where we're up to in it
+    left: Option<u128>,       // how many more candidates we can make
+}
+
+fn add_chains(
+    words: &[Vec<Vec<u8>>],
+    len: usize,
+    lens: &mut Vec<usize>,
+    settings: &Settings,
+    chains: &mut Vec<Chain>,
+) {
+    // Every way of making up len bytes out of the word lengths we have /*{{{*/
+    if len == 0 {
+        if lens.len() >= settings.elem_cnt_min {
+            let keyspace = lens
+                .iter()
+                .try_fold(1_u128, |total, l| total.checked_mul(words[*l].len() as u128));
+            // anything that big isn't getting finished anyway
+            if let Some(keyspace) = keyspace {
+                chains.push(Chain {
+                    lens: lens.clone(),
+                    keyspace,
+                });
+            }
+        }
+        return;
+    }
+    if lens.len() == settings.elem_cnt_max {
+        return;
+    }
+    for l in 1..=len {
+        if words[l].is_empty() {
+            continue;
+        }
+        lens.push(l);
+        add_chains(words, len - l, lens, settings, chains);
+        lens.pop();
+    }
+}
+/*}}}*/
+
+impl Prince {
+    pub fn new(path: &str, settings: &Settings) -> Result<Prince, Box<dyn Error>> {
+        // Load the words and work out every chain we're going to make /*{{{*/
+        let contents = match path {
+            "-" => {
+                let mut contents = Vec::new();
+                std::io::stdin().read_to_end(&mut contents)?;
+                contents
+            }
+            _ => match compressed::detect(path)? {
+                Some(compression) => {
+                    let mut contents = Vec::new();
+                    compressed::open(path, compression, Default::default())?.read_to_end(&mut contents)?;
+                    contents
+                }
+                None => fs::read(path)?,
+            },
+        };
+        let mut words: Vec<Vec<Vec<u8>>> = vec![Vec::new(); settings.pw_max + 1];
+        let mut seen = HashSet::new();
+        let mut unhexed: Vec<u8> = Vec::new();
+        for line in contents.split(|c| *c == 10) {
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            let word = encoding::unhex(line, &mut unhexed);
+            // newlines would split the candidate up again in the threads
+            if word.is_empty() || word.len() > settings.pw_max || word.contains(&10) {
+                continue;
+            }
+            if seen.insert(word.to_vec()) {
+                words[word.len()].push(word.to_vec());
+            }
+        }
+        if seen.is_empty() {
+            return Err(format!("No words in {path} short enough to chain").into());
+        }
+
+        let mut chains = Vec::new();
+        for len in settings.pw_min..=settings.pw_max {
+            add_chains(&words, len, &mut Vec::new(), settings, &mut chains);
+        }
+        // the smaller chains are the likelier ones, so they go first
+        chains.sort_by_key(|chain| chain.keyspace);
+
+        let mut prince = Prince {
+            words,
+            chains,
+            chain: 0,
+            next: 0,
+            left: settings.limit,
+        };
+        // skip whole chains until we get to the one the skip ends in
+        let mut skip = settings.skip;
+        while let Some(chain) = prince.chains.get(prince.chain) {
+            if skip < chain.keyspace {
+                prince.next = skip;
+                break;
+            }
+            skip -= chain.keyspace;
+            prince.chain += 1;
+        }
+        Ok(prince)
+    }
+    /*}}}*/
+
+    pub fn keyspace(&self) -> u128 {
+        self.chains.iter().map(|chain| chain.keyspace).sum()
+    }
+
+    pub fn chains(&self) -> usize {
+        self.chains.len()
+    }
+}
+
+impl Generator for Prince {
+    fn fill(&mut self, chunk: &mut Vec<u8>, size: usize) -> bool {
+        // Make the next candidates of the current chain, then the next chain /*{{{*/
+        while chunk.len() < size {
+            if self.left == Some(0) {
+                return false;
+            }
+            let Some(chain) = self.chains.get(self.chain) else {
+                return false;
+            };
+            if self.next == chain.keyspace {
+                self.chain += 1;
+                self.next = 0;
+                continue;
+            }
+            // the last word changes fastest
+            let start = chunk.len();
+            let mut index = self.next;
+            for len in chain.lens.iter().rev() {
+                let words = &self.words[*len];
+                let word = &words[(index % words.len() as u128) as usize];
+                index /= words.len() as u128;
+                chunk.extend_from_slice(word);
+            }
+            // we wrote the words in backwards, put them the right way round
+            chunk[start..].reverse();
+            let mut pos = start;
+            for len in &chain.lens {
+                chunk[pos..pos + len].reverse();
+                pos += len;
+            }
+            chunk.push(10);
+            self.next += 1;
+            if let Some(left) = &mut self.left {
+                *left -= 1;
+            }
+        }
+        true
+    }
+    /*}}}*/
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(pw: (usize, usize), elem_cnt: (usize, usize)) -> Settings {
+        Settings {
+            pw_min: pw.0,
+            pw_max: pw.1,
+            elem_cnt_min: elem_cnt.0,
+            elem_cnt_max: elem_cnt.1,
+            skip: 0,
+            limit: None,
+        }
+    }
+
+    fn candidates(name: &str, words: &str, settings: &Settings) -> Vec<String> {
+        let path = std::env::temp_dir().join(format!("ntcrack-{}-{name}", std::process::id()));
+        fs::write(&path, words).unwrap();
+        let prince = Prince::new(&path.display().to_string(), settings);
+        fs::remove_file(&path).unwrap();
+        let mut prince = prince.unwrap();
+        let mut chunk = Vec::new();
+        // a bit at a time so it has to carry on where it left off
+        let mut size = 0;
+        loop {
+            size += 16;
+            if !prince.fill(&mut chunk, size) {
+                break;
+            }
+        }
+        String::from_utf8(chunk).unwrap().lines().map(String::from).collect()
+    }
+
+    #[test]
+    fn smallest_chains_first() {
+        let all = candidates("order", "a\nb\nc\nxy\n", &settings((2, 3), (1, 3)));
+        // xy, then a+xy and xy+a, then a+a, then a+a+a
+        assert_eq!(all.len(), 1 + 3 + 3 + 9 + 27);
+        assert_eq!(&all[..8], ["xy", "axy", "bxy", "cxy", "xya", "xyb", "xyc", "aa"]);
+        assert_eq!(all[7..16].concat(), "aaabacbabbbccacbcc");
+        assert_eq!(all[16], "aaa");
+        assert_eq!(all.last().unwrap(), "ccc");
+        let unique: HashSet<&String> = all.iter().collect();
+        assert_eq!(unique.len(), all.len());
+    }
+
+    #[test]
+    fn limits_are_kept_to() {
+        // too long a word is dropped, and so are chains of the wrong length or
+        // number of words
+        let all = candidates("limits", "a\nb\nxy\nlong\n", &settings((3, 3), (2, 2)));
+        assert_eq!(all, ["axy", "bxy", "xya", "xyb"]);
+        // the one two letter word is a smaller chain than the two one letter ones
+        let all = candidates("one", "a\nb\nxy\n", &settings((1, 2), (1, 1)));
+        assert_eq!(all, ["xy", "a", "b"]);
+    }
+
+    #[test]
+    fn skip_and_limit() {
+        let mut settings = settings((2, 3), (1, 3));
+        settings.skip = 2;
+        settings.limit = Some(4);
+        let all = candidates("skip", "a\nb\nc\nxy\n", &settings);
+        assert_eq!(all, ["bxy", "cxy", "xya", "xyb"]);
+    }
+}
@@ -0,0 +1,373 @@
+// A hashcat/john compatible rule engine for mangling candidates in the threads
+
+use std::error::Error;
+use std::fs;
+
+// Same limit as hashcat, anything longer is rejected
+const MAX_LEN: usize = 256;
+
+#[derive(Clone, Copy)]
+enum Op {
+    Nothing,                // :
+    Lower,                  // l
+    Upper,                  // u
+    Capitalise,             // c
+    InvertCapitalise,       // C
+    ToggleAll,              // t
+    Toggle(usize),          // TN
+    Reverse,                // r
+    Duplicate,              // d
+    DuplicateN(usize),      // pN
+    Reflect,                // f
+    RotateLeft,             // {
+    RotateRight,            // }
+    Append(u8),             // $X
+    Prepend(u8),            // ^X
+    DeleteFirst,            // [
+    DeleteLast,             // ]
+    DeleteAt(usize),        // DN
+    Extract(usize, usize),  // xNM
+    Omit(usize, usize),     // ONM
+    Insert(usize, u8),      // iNX
+    Overwrite(usize, u8),   // oNX
+    Truncate(usize),        // 'N
+    Replace(u8, u8),        // sXY
+    Purge(u8),              // @X
+    DupFirst(usize),        // zN
+    DupLast(usize),         // ZN
+    DupAll,                 // q
+    SwapFront,              // k
+    SwapBack,               // K
+    Swap(usize, usize),     // *NM
+    ShiftLeft(usize),       // LN
+    ShiftRight(usize),      // RN
+    Increment(usize),       // +N
+    Decrement(usize),       // -N
+    ReplaceNext(usize),     // .N
+    ReplacePrev(usize),     // ,N
+    DupBlockFront(usize),   // yN
+    DupBlockBack(usize),    // YN
+    Title,                  // E
+    TitleSep(u8),           // eX
+    ToggleAfter(usize, u8), // 3NX
+}
+
+pub struct Rule(Vec<Op>);
+
+fn position(c: u8) -> Option<usize> {
+    // Rule positions are 0-9 then A-Z for 10-35 /*{{{*/
+    match c {
+        b'0'..=b'9' => Some((c - b'0') as usize),
+        b'A'..=b'Z' => Some((c - b'A') as usize + 10),
+        _ => None,
+    }
+}
+/*}}}*/
+
+pub fn parse_rule(line: &[u8]) -> Result<Rule, String> {
+    // Parse a single line of rule functions /*{{{*/
+    let mut ops = Vec::new();
+    let mut bytes = line.iter().copied();
+    while let Some(f) = bytes.next() {
+        let missing = || format!("'{}' is missing an argument", f as char);
+        let mut arg = || bytes.next().ok_or_else(missing);
+        let pos = |c: Result<u8, String>| -> Result<usize, String> {
+            let c = c?;
+            position(c).ok_or(format!("'{}' is not a valid position", c as char))
+        };
+        let op = match f {
+            b' ' | b'\t' => continue, // whitespace between functions is allowed
+            b':' => Op::Nothing,
+            b'l' => Op::Lower,
+            b'u' => Op::Upper,
+            b'c' => Op::Capitalise,
+            b'C' => Op::InvertCapitalise,
+            b't' => Op::ToggleAll,
+            b'T' => Op::Toggle(pos(arg())?),
+            b'r' => Op::Reverse,
+            b'd' => Op::Duplicate,
+            b'p' => Op::DuplicateN(pos(arg())?),
+            b'f' => Op::Reflect,
+            b'{' => Op::RotateLeft,
+            b'}' => Op::RotateRight,
+            b'$' => Op::Append(arg()?),
+            b'^' => Op::Prepend(arg()?),
+            b'[' => Op::DeleteFirst,
+            b']' => Op::DeleteLast,
+            b'D' => Op::DeleteAt(pos(arg())?),
+            b'x' => Op::Extract(pos(arg())?, pos(arg())?),
+            b'O' => Op::Omit(pos(arg())?, pos(arg())?),
+            b'i' => Op::Insert(pos(arg())?, arg()?),
+            b'o' => Op::Overwrite(pos(arg())?, arg()?),
+            b'\'' => Op::Truncate(pos(arg())?),
+            b's' => Op::Replace(arg()?, arg()?),
+            b'@' => Op::Purge(arg()?),
+            b'z' => Op::DupFirst(pos(arg())?),
+            b'Z' => Op::DupLast(pos(arg())?),
+            b'q' => Op::DupAll,
+            b'k' => Op::SwapFront,
+            b'K' => Op::SwapBack,
+            b'*' => Op::Swap(pos(arg())?, pos(arg())?),
+            b'L' => Op::ShiftLeft(pos(arg())?),
+            b'R' => Op::ShiftRight(pos(arg())?),
+            b'+' => Op::Increment(pos(arg())?),
+            b'-' => Op::Decrement(pos(arg())?),
+            b'.' => Op::ReplaceNext(pos(arg())?),
+            b',' => Op::ReplacePrev(pos(arg())?),
+            b'y' => Op::DupBlockFront(pos(arg())?),
+            b'Y' => Op::DupBlockBack(pos(arg())?),
+            b'E' => Op::Title,
+            b'e' => Op::TitleSep(arg()?),
+            b'3' => Op::ToggleAfter(pos(arg())?, arg()?),
+            _ => return Err(format!("unsupported rule function '{}'", f as char)),
+        };
+        ops.push(op);
+    }
+    Ok(Rule(ops))
+}
+/*}}}*/
+
+pub fn load_rules(path: &str) -> Result<Vec<Rule>, Box<dyn Error>> {
+    // Read a rule file, skipping comments and reporting rules we can't use /*{{{*/
+    let contents = fs::read(path)?;
+    let mut rules = Vec::new();
+    let mut bad_rules = 0;
+    for (lineno, line) in contents.split(|c| *c == b'\n').enumerate() {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        if line.is_empty() || line.starts_with(b"#") {
+            continue;
+        }
+        match parse_rule(line) {
+            Ok(rule) => rules.push(rule),
+            Err(e) => {
+                bad_rules += 1;
+                if bad_rules <= 10 {
+                    info!("[!] Skipping rule on line {} of {path}: {e}", lineno + 1);
+                }
+            }
+        }
+    }
+    if bad_rules > 0 {
+        info!("[!] Skipped {bad_rules} rules in {path} that we don't support");
+    }
+    if rules.is_empty() {
+        return Err(format!("No usable rules found in {path}").into());
+    }
+    Ok(rules)
+}
+/*}}}*/
+
+fn toggle(c: &mut u8) {
+    if c.is_ascii_lowercase() {
+        c.make_ascii_uppercase();
+    } else if c.is_ascii_uppercase() {
+        c.make_ascii_lowercase();
+    }
+}
+
+fn title(word: &mut [u8], sep: u8) {
+    // Lowercase everything, then uppercase the start and after each separator /*{{{*/
+    word.make_ascii_lowercase();
+    let mut upper = true;
+    for c in word.iter_mut() {
+        if upper {
+            c.make_ascii_uppercase();
+        }
+        upper = *c == sep;
+    }
+}
+/*}}}*/
+
+impl Rule {
+    pub fn apply(&self, word: &[u8], out: &mut Vec<u8>) -> bool {
+        // Mangle word into out, returns false if the result should be skipped /*{{{*/
+        // Like hashcat, functions given a position past the end of the word
+        // leave it unchanged
+        out.clear();
+        out.extend_from_slice(word);
+        for op in &self.0 {
+            let len = out.len();
+            match *op {
+                Op::Nothing => {}
+                Op::Lower => out.make_ascii_lowercase(),
+                Op::Upper => out.make_ascii_uppercase(),
+                Op::Capitalise => {
+                    out.make_ascii_lowercase();
+                    if let Some(c) = out.first_mut() {
+                        c.make_ascii_uppercase();
+                    }
+                }
+                Op::InvertCapitalise => {
+                    out.make_ascii_uppercase();
+                    if let Some(c) = out.first_mut() {
+                        c.make_ascii_lowercase();
+                    }
+                }
+                Op::ToggleAll => out.iter_mut().for_each(toggle),
+                Op::Toggle(n) => {
+                    if let Some(c) = out.get_mut(n) {
+                        toggle(c);
+                    }
+                }
+                Op::Reverse => out.reverse(),
+                Op::Duplicate => out.extend_from_within(..),
+                Op::DuplicateN(n) => {
+                    for _ in 0..n {
+                        out.extend_from_within(..len);
+                    }
+                }
+                Op::Reflect => {
+                    out.extend_from_within(..);
+                    out[len..].reverse();
+                }
+                Op::RotateLeft if len > 0 => out.rotate_left(1),
+                Op::RotateRight if len > 0 => out.rotate_right(1),
+                Op::RotateLeft | Op::RotateRight => {}
+                Op::Append(c) => out.push(c),
+                Op::Prepend(c) => out.insert(0, c),
+                Op::DeleteFirst if len > 0 => {
+                    out.remove(0);
+                }
+                Op::DeleteLast => {
+                    out.pop();
+                }
+                Op::DeleteFirst => {}
+                Op::DeleteAt(n) if n < len => {
+                    out.remove(n);
+                }
+                Op::Extract(n, m) if n + m <= len => {
+                    out.truncate(n + m);
+                    out.drain(..n);
+                }
+                Op::Omit(n, m) if n + m <= len => {
+                    out.drain(n..n + m);
+                }
+                Op::Insert(n, c) if n <= len => out.insert(n, c),
+                Op::Overwrite(n, c) if n < len => out[n] = c,
+                Op::Truncate(n) => out.truncate(n),
+                Op::Replace(x, y) => out.iter_mut().filter(|c| **c == x).for_each(|c| *c = y),
+                Op::Purge(x) => out.retain(|c| *c != x),
+                Op::DupFirst(n) if len > 0 => {
+                    out.splice(0..0, std::iter::repeat_n(out[0], n));
+                }
+                Op::DupLast(n) if len > 0 => out.extend(std::iter::repeat_n(out[len - 1], n)),
+                Op::DupAll => {
+                    let doubled: Vec<u8> = out.iter().flat_map(|c| [*c, *c]).collect();
+                    *out = doubled;
+                }
+                Op::SwapFront if len > 1 => out.swap(0, 1),
+                Op::SwapBack if len > 1 => out.swap(len - 1, len - 2),
+                Op::Swap(n, m) if n < len && m < len => out.swap(n, m),
+                Op::ShiftLeft(n) if n < len => out[n] <<= 1,
+                Op::ShiftRight(n) if n < len => out[n] >>= 1,
+                Op::Increment(n) if n < len => out[n] = out[n].wrapping_add(1),
+                Op::Decrement(n) if n < len => out[n] = out[n].wrapping_sub(1),
+                Op::ReplaceNext(n) if n + 1 < len => out[n] = out[n + 1],
+                Op::ReplacePrev(n) if n > 0 && n < len => out[n] = out[n - 1],
+                Op::DupBlockFront(n) if n <= len => {
+                    let block = out[..n].to_vec();
+                    out.splice(0..0, block);
+                }
+                Op::DupBlockBack(n) if n <= len => out.extend_from_within(len - n..),
+                Op::Title => title(out, b' '),
+                Op::TitleSep(sep) => title(out, sep),
+                Op::ToggleAfter(n, x) => {
+                    // toggle the character after the Nth+1 instance of x
+                    let after = out
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, c)| **c == x)
+                        .nth(n)
+                        .map(|(i, _)| i + 1);
+                    if let Some(c) = after.and_then(|i| out.get_mut(i)) {
+                        toggle(c);
+                    }
+                }
+                // the rest are positional functions given an out of range position
+                _ => {}
+            }
+            if out.len() > MAX_LEN {
+                return false;
+            }
+        }
+        !out.is_empty()
+    }
+    /*}}}*/
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mangle(rule: &str, word: &str) -> Option<String> {
+        // Apply a rule to a word, None if the result is skipped
+        let mut out = Vec::new();
+        match parse_rule(rule.as_bytes()).unwrap().apply(word.as_bytes(), &mut out) {
+            true => Some(String::from_utf8(out).unwrap()),
+            false => None,
+        }
+    }
+
+    #[test]
+    fn positions_in_range() {
+        assert_eq!(mangle("T0", "password").as_deref(), Some("Password"));
+        assert_eq!(mangle("TA", "abcdefghijkl").as_deref(), Some("abcdefghijKl"));
+        assert_eq!(mangle("x14", "password").as_deref(), Some("assw"));
+        assert_eq!(mangle("O12", "password").as_deref(), Some("psword"));
+        assert_eq!(mangle("i3!", "pass").as_deref(), Some("pas!s"));
+        assert_eq!(mangle("i4!", "pass").as_deref(), Some("pass!"));
+        assert_eq!(mangle("o0X", "pass").as_deref(), Some("Xass"));
+        assert_eq!(mangle("'4", "password").as_deref(), Some("pass"));
+    }
+
+    #[test]
+    fn positions_out_of_range_leave_the_word() {
+        assert_eq!(mangle("T8", "password").as_deref(), Some("password"));
+        assert_eq!(mangle("x58", "password").as_deref(), Some("password"));
+        assert_eq!(mangle("O71", "password").as_deref(), Some("passwor"));
+        assert_eq!(mangle("O72", "password").as_deref(), Some("password"));
+        assert_eq!(mangle("i5!", "pass").as_deref(), Some("pass"));
+        assert_eq!(mangle("o4X", "pass").as_deref(), Some("pass"));
+        assert_eq!(mangle("'9", "pass").as_deref(), Some("pass"));
+        // truncating to nothing leaves nothing worth trying
+        assert_eq!(mangle("'0", "pass"), None);
+    }
+
+    #[test]
+    fn duplicate_first_and_last() {
+        assert_eq!(mangle("z2", "abc").as_deref(), Some("aaabc"));
+        assert_eq!(mangle("Z2", "abc").as_deref(), Some("abccc"));
+        assert_eq!(mangle("z0", "abc").as_deref(), Some("abc"));
+        assert_eq!(mangle("[z3", "a"), None);
+        assert_eq!(mangle("]Z3", "a"), None);
+    }
+
+    #[test]
+    fn toggle_after_instance() {
+        assert_eq!(mangle("30-", "a-b-c").as_deref(), Some("a-B-c"));
+        assert_eq!(mangle("31-", "a-b-c").as_deref(), Some("a-b-C"));
+        assert_eq!(mangle("32-", "a-b-c").as_deref(), Some("a-b-c"));
+        // nothing after the last one to toggle
+        assert_eq!(mangle("30-", "ab-").as_deref(), Some("ab-"));
+    }
+
+    #[test]
+    fn too_long_is_skipped() {
+        let half = "a".repeat(MAX_LEN / 2);
+        assert_eq!(mangle("d", &half).map(|w| w.len()), Some(MAX_LEN));
+        assert_eq!(mangle("d$a", &half), None);
+        assert_eq!(mangle("p9", "abcdefghijklmnopqrstuvwxyz"), None);
+        // even if a later function would have brought it back under
+        assert_eq!(mangle("d$a'1", &half), None);
+    }
+
+    #[test]
+    fn bad_rules() {
+        assert!(parse_rule(b"T").is_err());
+        assert!(parse_rule(b"Ta").is_err());
+        assert!(parse_rule(b"x1").is_err());
+        assert!(parse_rule(b"i5").is_err());
+        assert!(parse_rule(b"X").is_err());
+        assert!(parse_rule(b"$1 $2").is_ok());
+    }
+}
@@ -0,0 +1,205 @@
+// Saving how far through the wordlists a run got, so --restore can carry on from there
+
+use crate::encoding;
+use crate::Stats;
+use std::error::Error;
+use std::fs;
+use std::io::Write;
+
+pub struct Session {
+    // Everything needed to pick a run back up /*{{{*/
+    pub args: Vec<String>,      // the command line it was started with
+    pub hashlist: String,       // the full path, so another one with the same name isn't mistaken for it
+    pub hashlist_size: u64,
+    pub wordlists: Vec<String>, // after directories and the manifest were expanded
+    pub position: (usize, u64), // the wordlist and offset in it that everything before is done
+    pub stats: Stats,
+    pub elapsed: f64, // seconds spent getting there, so the speeds come out right
+}
+/*}}}*/
+
+pub fn identify(path: &str) -> (String, u64) {
+    // The full path and size of a hashlist, to tell if a restore is on the same one /*{{{*/
+    let full = fs::canonicalize(path).map_or_else(|_| path.to_string(), |full| full.display().to_string());
+    (full, fs::metadata(path).map_or(0, |m| m.len()))
+}
+/*}}}*/
+
+fn write_field(out: &mut Vec<u8>, name: &str, value: &str) {
+    // Write a name value line, as $HEX[] if the value would break the line up /*{{{*/
+    out.extend_from_slice(name.as_bytes());
+    out.push(b' ');
+    encoding::write_hex(out, value.as_bytes());
+    out.push(b'\n');
+}
+/*}}}*/
+
+impl Session {
+    pub fn save(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        // Write the session out, replacing the old one in one go /*{{{*/
+        let mut out: Vec<u8> = Vec::new();
+        writeln!(out, "# ntcrack session v1")?;
+        for arg in &self.args {
+            write_field(&mut out, "arg", arg);
+        }
+        write_field(&mut out, "hashlist", &self.hashlist);
+        writeln!(out, "hashlist_size {}", self.hashlist_size)?;
+        for wordlist in &self.wordlists {
+            write_field(&mut out, "wordlist", wordlist);
+        }
+        writeln!(out, "position {} {}", self.position.0, self.position.1)?;
+        let s = &self.stats;
+        writeln!(out, "stats {} {} {} {} {}", s.hashed, s.cracked, s.waits, s.kbs, s.compressed_kbs)?;
+        writeln!(out, "elapsed {:.3}", self.elapsed)?;
+        // a sleep or crash half way through writing shouldn't lose the last one
+        let tmp = format!("{path}.tmp");
+        fs::write(&tmp, out)?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+    /*}}}*/
+
+    pub fn load(path: &str) -> Result<Session, Box<dyn Error>> {
+        // Read a session written by save() /*{{{*/
+        let contents = fs::read(path).map_err(|e| format!("Failed to read the session {path}: {e}"))?;
+        let mut session = Session {
+            args: Vec::new(),
+            hashlist: String::new(),
+            hashlist_size: 0,
+            wordlists: Vec::new(),
+            position: (0, 0),
+            stats: Stats::default(),
+            elapsed: 0.0,
+        };
+        let mut unhexed: Vec<u8> = Vec::new();
+        for (lineno, line) in contents.split(|c| *c == 10).enumerate() {
+            if line.is_empty() || line.starts_with(b"#") {
+                continue;
+            }
+            let bad = || format!("Bad line {} in session {path}", lineno + 1);
+            let (name, value) = match line.iter().position(|c| *c == b' ') {
+                Some(space) => (&line[..space], &line[space + 1..]),
+                None => return Err(bad().into()),
+            };
+            let value = encoding::unhex(value, &mut unhexed);
+            let value = String::from_utf8(value.to_vec()).map_err(|_| bad())?;
+            let numbers = || -> Result<Vec<u64>, String> {
+                value.split(' ').map(|n| n.parse::<u64>().map_err(|_| bad())).collect()
+            };
+            match name {
+                b"arg" => session.args.push(value),
+                b"hashlist" => session.hashlist = value,
+                b"hashlist_size" => session.hashlist_size = value.parse().map_err(|_| bad())?,
+                b"wordlist" => session.wordlists.push(value),
+                b"position" => match numbers()?.as_slice() {
+                    &[wordlist, offset] => session.position = (wordlist as usize, offset),
+                    _ => return Err(bad().into()),
+                },
+                b"stats" => match numbers()?.as_slice() {
+                    &[hashed, cracked, waits, kbs, compressed_kbs] => {
+                        session.stats = Stats {
+                            hashed: hashed as usize,
+                            cracked: cracked as usize,
+                            waits: waits as usize,
+                            kbs: kbs as usize,
+                            compressed_kbs: compressed_kbs as usize,
+                        }
+                    }
+                    _ => return Err(bad().into()),
+                },
+                b"elapsed" => session.elapsed = value.parse().map_err(|_| bad())?,
+                _ => return Err(bad().into()),
+            }
+        }
+        if session.args.is_empty() || session.wordlists.is_empty() {
+            return Err(format!("Session {path} has no command line or wordlists to restore").into());
+        }
+        Ok(session)
+    }
+    /*}}}*/
+
+    pub fn check_hashlist(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        // Make sure a restore is going on with the hashlist the session was started on /*{{{*/
+        let (full, size) = identify(path);
+        if full != self.hashlist {
+            return Err(format!("The session was started on the hashlist {}, not {full}", self.hashlist).into());
+        }
+        if size != self.hashlist_size {
+            info!("[!] The hashlist has changed since the session was saved, what was read before won't be tried against any new hashes");
+        }
+        Ok(())
+    }
+    /*}}}*/
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp(name: &str) -> String {
+        let dir = std::env::temp_dir();
+        dir.join(format!("ntcrack-{}-{name}", std::process::id())).display().to_string()
+    }
+
+    fn session() -> Session {
+        Session {
+            args: vec![
+                "-r".to_string(),
+                "my rules/best64.rule".to_string(),
+                "C:\\hashes.txt".to_string(),
+                "$HEX[41]".to_string(),
+                "two\nlines".to_string(),
+            ],
+            hashlist: "/tmp/crack me.txt".to_string(),
+            hashlist_size: 1234,
+            wordlists: vec!["rockyou.txt".to_string(), "wl:2.txt".to_string()],
+            position: (1, 5_248_007),
+            stats: Stats { cracked: 3, hashed: 27_724_020, waits: 4, kbs: 30_750, compressed_kbs: 12 },
+            elapsed: 4.008,
+        }
+    }
+
+    #[test]
+    fn save_then_load() {
+        let path = temp("round-trip");
+        let saved = session();
+        saved.save(&path).unwrap();
+        let loaded = Session::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.args, saved.args);
+        assert_eq!(loaded.hashlist, saved.hashlist);
+        assert_eq!(loaded.hashlist_size, saved.hashlist_size);
+        assert_eq!(loaded.wordlists, saved.wordlists);
+        assert_eq!(loaded.position, saved.position);
+        let (l, s) = (loaded.stats, saved.stats);
+        assert_eq!(
+            (l.cracked, l.hashed, l.waits, l.kbs, l.compressed_kbs),
+            (s.cracked, s.hashed, s.waits, s.kbs, s.compressed_kbs)
+        );
+        assert_eq!(loaded.elapsed, saved.elapsed);
+    }
+
+    #[test]
+    fn bad_lines_are_errors() {
+        let path = temp("bad-lines");
+        session().save(&path).unwrap();
+        let good = fs::read_to_string(&path).unwrap();
+        for bad in [
+            "sta",             // cut off part way through
+            "position 1",      // not enough numbers
+            "position 1 2 3",  // too many
+            "stats 1 2 x 4 5", // not a number
+            "elapsed soon",
+            "wordlist",    // no value
+            "colour blue", // not something we save
+        ] {
+            fs::write(&path, format!("{good}{bad}\n")).unwrap();
+            let e = Session::load(&path).err().unwrap().to_string();
+            assert!(e.starts_with("Bad line"), "{bad}: {e}");
+        }
+        // a session with nothing to restore
+        fs::write(&path, "# ntcrack session v1\nposition 0 0\n").unwrap();
+        assert!(Session::load(&path).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,89 @@
+// Guessing passwords from the account names in pwdump/secretsdump hashlists
+
+use std::collections::HashSet;
+use std::ops::RangeInclusive;
+
+// What people stick on the end of their name to get past the complexity rules
+const SUFFIXES: &[&str] = &[
+    "", "0", "1", "2", "3", "4", "5", "6", "7", "8", "9", "01", "12", "123", "1234", "!", "1!",
+    "123!", "@", "#",
+];
+
+// Characters that split an account name into its parts, e.g. first.last
+const SEPARATORS: &[char] = &['.', '_', '-', ' '];
+
+fn title(word: &str) -> String {
+    // Uppercase the start of each part and lowercase the rest /*{{{*/
+    let mut upper = true;
+    word.chars()
+        .map(|c| {
+            let c = match upper {
+                true => c.to_uppercase().next().unwrap_or(c),
+                false => c.to_lowercase().next().unwrap_or(c),
+            };
+            upper = SEPARATORS.contains(&c);
+            c
+        })
+        .collect()
+}
+/*}}}*/
+
+fn bases(username: &str, domain: Option<&str>) -> Vec<String> {
+    // The words to build guesses from, the name, its parts and the domain /*{{{*/
+    let mut bases = vec![username.to_string()];
+    let parts: Vec<&str> = username.split(SEPARATORS).filter(|p| !p.is_empty()).collect();
+    if parts.len() > 1 {
+        let first = parts[0];
+        let last = parts[parts.len() - 1];
+        bases.push(parts.concat());
+        bases.push(first.to_string());
+        bases.push(last.to_string());
+        // jsmith and johns
+        let initial = |part: &str| part.chars().next().map(String::from).unwrap_or_default();
+        bases.push(initial(first) + last);
+        bases.push(first.to_string() + &initial(last));
+    }
+    if let Some(domain) = domain.filter(|d| !d.is_empty()) {
+        bases.push(domain.to_string());
+    }
+    bases
+}
+/*}}}*/
+
+pub fn candidates(username: &str, domain: Option<&str>, years: RangeInclusive<u32>) -> Vec<Vec<u8>> {
+    // Every guess for an account, without duplicates /*{{{*/
+    // Machine accounts have long random passwords, don't bother
+    if username.is_empty() || username.ends_with('$') {
+        return Vec::new();
+    }
+    let mut words: Vec<String> = Vec::new();
+    for base in bases(username, domain) {
+        let reversed: String = base.chars().rev().collect();
+        words.push(base.to_lowercase());
+        words.push(base.to_uppercase());
+        words.push(title(&base));
+        words.push(reversed.to_lowercase());
+        words.push(title(&reversed));
+        words.push(reversed);
+        words.push(base);
+    }
+    let mut suffixes: Vec<String> = SUFFIXES.iter().map(|s| s.to_string()).collect();
+    for year in years {
+        suffixes.push(format!("{year}"));
+        suffixes.push(format!("{year}!"));
+        suffixes.push(format!("{:02}", year % 100));
+    }
+
+    let mut seen = HashSet::new();
+    let mut candidates = Vec::new();
+    for word in &words {
+        for suffix in &suffixes {
+            let candidate = format!("{word}{suffix}").into_bytes();
+            if seen.insert(candidate.clone()) {
+                candidates.push(candidate);
+            }
+        }
+    }
+    candidates
+}
+/*}}}*/